@@ -0,0 +1,323 @@
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::interrupt;
+
+/// Extra room on top of the archive's uncompressed size needed to extract
+const DISK_SPACE_MARGIN: f64 = 1.25;
+
+/// Every zip archive with at least one entry starts with a local file header
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// How much of a file that isn't a zip archive to quote in the error
+const PREVIEW_LEN: usize = 64;
+
+pub(crate) fn sha256_file(filename: &Path) -> Result<String> {
+    let mut file = File::open(filename)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub fn verify_checksum(filename: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(filename)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(Error::Checksum { expected: expected.to_string(), actual });
+    }
+
+    Ok(())
+}
+
+/// Fails early when a download isn't a zip archive at all, such as an HTML
+/// maintenance page served with a 200, quoting the start of what was received.
+pub fn check_zip_magic(filename: &Path, content_type: Option<&str>) -> Result<()> {
+    let mut start = Vec::with_capacity(PREVIEW_LEN);
+    File::open(filename)?.take(PREVIEW_LEN as u64).read_to_end(&mut start)?;
+
+    let content_type = content_type.unwrap_or("none");
+    if start.starts_with(ZIP_MAGIC) {
+        if !is_zip_content_type(content_type) {
+            debug!("Archive served with unexpected Content-Type: {}", content_type);
+        }
+        return Ok(());
+    }
+
+    Err(Error::extract(format!(
+        "Downloaded file is not a zip archive (Content-Type: {}), it starts with {:?}",
+        content_type,
+        String::from_utf8_lossy(&start)
+    )))
+}
+
+fn is_zip_content_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    matches!(mime, "application/zip" | "application/x-zip-compressed" | "application/octet-stream")
+}
+
+pub fn open_archive(filename: &Path) -> Result<ZipArchive<File>> {
+    let file = File::open(filename)?;
+    ZipArchive::new(file).map_err(|source| Error::Extract {
+        message: format!("`{}` is not a valid zip archive", filename.display()),
+        source: Some(source),
+    })
+}
+
+fn invalid_entry(source: zip::result::ZipError) -> Error {
+    Error::Extract { message: "could not read archive entry".to_string(), source: Some(source) }
+}
+
+fn top_level_dir(path: &Path) -> Option<String> {
+    let top = path.components().next()?;
+    Some(top.as_os_str().to_string_lossy().into_owned())
+}
+
+/// The single directory everything in the archive is wrapped in, such as
+/// `ElvUI-13.21/` in a GitHub zipball, when none of `directories` is at the
+/// root itself.
+fn wrapping_dir<R: Read + Seek>(archive: &mut ZipArchive<R>, directories: &[String]) -> Result<Option<PathBuf>> {
+    let mut tops = HashSet::new();
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i).map_err(invalid_entry)?;
+        let path = match entry.enclosed_name() {
+            Some(path) => path,
+            None => continue,
+        };
+        // A file at the root means nothing is wrapped
+        if path.components().count() < 2 && !entry.is_dir() {
+            return Ok(None);
+        }
+        tops.extend(top_level_dir(path));
+    }
+
+    match tops.into_iter().collect::<Vec<_>>().as_slice() {
+        [top] if !directories.contains(top) => {
+            debug!("Archive is wrapped in {}/, descending into it", top);
+            Ok(Some(PathBuf::from(top)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// `path` relative to the wrapping directory, if there is one.
+fn unwrapped<'a>(path: &'a Path, wrapper: Option<&Path>) -> Option<&'a Path> {
+    match wrapper {
+        Some(wrapper) => path.strip_prefix(wrapper).ok(),
+        None => Some(path),
+    }
+}
+
+/// The space needed to extract `directories`, from the uncompressed sizes in
+/// the archive's central directory plus a margin for filesystem overhead.
+pub fn required_space<R: Read + Seek>(archive: &mut ZipArchive<R>, directories: &[String]) -> Result<u64> {
+    let wrapper = wrapping_dir(archive, directories)?;
+    let mut total: u64 = 0;
+
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i).map_err(invalid_entry)?;
+        let managed = entry
+            .enclosed_name()
+            .and_then(|path| unwrapped(path, wrapper.as_deref()))
+            .and_then(top_level_dir)
+            .is_some_and(|top| directories.contains(&top));
+        if managed {
+            total += entry.size();
+        }
+    }
+
+    Ok((total as f64 * DISK_SPACE_MARGIN) as u64)
+}
+
+/// Extracts only the entries under `directories` from the archive into
+/// `dest`, failing if any of them isn't in the archive at all. An archive
+/// with everything inside a single wrapping directory is extracted from
+/// within it. Entries with unsafe paths (absolute, or escaping `dest`) are
+/// skipped.
+pub fn extract_directories<R: Read + Seek>(archive: &mut ZipArchive<R>, dest: &Path, directories: &[String]) -> Result<()> {
+    let wrapper = wrapping_dir(archive, directories)?;
+    let mut found = HashSet::new();
+
+    for i in 0..archive.len() {
+        interrupt::check()?;
+        let mut entry = archive.by_index(i).map_err(invalid_entry)?;
+        let path = match entry.enclosed_name() {
+            Some(path) => path.to_owned(),
+            None => {
+                debug!("Skipping archive entry with unsafe path: {}", entry.name());
+                continue;
+            }
+        };
+        let path = match unwrapped(&path, wrapper.as_deref()) {
+            Some(path) => path.to_owned(),
+            None => continue,
+        };
+
+        let top = match top_level_dir(&path) {
+            Some(top) => top,
+            None => continue,
+        };
+        if !directories.contains(&top) {
+            continue;
+        }
+        found.insert(top);
+
+        let out_path = dest.join(&path);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&out_path)?;
+        } else {
+            if let Some(parent) = out_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut out = File::create(&out_path)?;
+            std::io::copy(&mut entry, &mut out)?;
+        }
+    }
+
+    let missing: Vec<&str> = directories
+        .iter()
+        .filter(|target| !found.contains(*target))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::extract(format!("Archive is missing expected directories: {}", missing.join(", "))));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install::check_disk_space;
+    use crate::test_support::{read_marker, sample_metadata, write_zip};
+    use tempfile::Builder;
+
+    #[test]
+    fn checksum_is_verified() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        std::fs::write(&filename, "abc").unwrap();
+
+        let expected = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        assert!(verify_checksum(&filename, expected).is_ok());
+        assert!(verify_checksum(&filename, "00").is_err());
+    }
+
+    #[test]
+    fn extracts_only_managed_directories() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        write_zip(&filename, &[
+            ("ElvUI/marker", "new"),
+            ("ElvUI_Options/marker", "new"),
+            ("README.md", "readme"),
+        ]);
+
+        let dest = dir.path().join("elvui");
+        let mut archive = ZipArchive::new(File::open(&filename).unwrap()).unwrap();
+        extract_directories(&mut archive, &dest, &sample_metadata().directories).unwrap();
+
+        assert_eq!(read_marker(&dest, "ElvUI"), "new");
+        assert_eq!(read_marker(&dest, "ElvUI_Options"), "new");
+        assert!(!dest.join("README.md").exists());
+    }
+
+    #[test]
+    fn extracts_from_wrapping_directory() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        write_zip(&filename, &[
+            ("ElvUI-13.21/ElvUI/marker", "new"),
+            ("ElvUI-13.21/ElvUI_Options/marker", "new"),
+            ("ElvUI-13.21/README.md", "readme"),
+        ]);
+
+        let directories = sample_metadata().directories;
+        let dest = dir.path().join("elvui");
+        let mut archive = ZipArchive::new(File::open(&filename).unwrap()).unwrap();
+        assert_eq!(required_space(&mut archive, &directories).unwrap(), 7);
+        extract_directories(&mut archive, &dest, &directories).unwrap();
+
+        assert_eq!(read_marker(&dest, "ElvUI"), "new");
+        assert_eq!(read_marker(&dest, "ElvUI_Options"), "new");
+        assert!(!dest.join("ElvUI-13.21").exists());
+    }
+
+    #[test]
+    fn single_managed_directory_is_not_a_wrapper() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        write_zip(&filename, &[("ElvUI/ElvUI/marker", "nested"), ("ElvUI/marker", "new")]);
+
+        let directories = vec!["ElvUI".to_string()];
+        let mut archive = ZipArchive::new(File::open(&filename).unwrap()).unwrap();
+        assert_eq!(wrapping_dir(&mut archive, &directories).unwrap(), None);
+
+        let dest = dir.path().join("elvui");
+        extract_directories(&mut archive, &dest, &directories).unwrap();
+        assert_eq!(read_marker(&dest, "ElvUI"), "new");
+    }
+
+    #[test]
+    fn extract_fails_on_missing_directory() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        write_zip(&filename, &[("ElvUI/marker", "new")]);
+
+        let dest = dir.path().join("elvui");
+        let mut archive = ZipArchive::new(File::open(&filename).unwrap()).unwrap();
+        let err = extract_directories(&mut archive, &dest, &sample_metadata().directories).unwrap_err();
+        assert!(err.to_string().contains("ElvUI_Options"));
+    }
+
+    #[test]
+    fn required_space_counts_managed_directories() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        write_zip(&filename, &[
+            ("ElvUI/core.lua", "1234"),
+            ("ElvUI_Options/options.lua", "1234"),
+            ("README.md", "not counted"),
+        ]);
+
+        let mut archive = ZipArchive::new(File::open(&filename).unwrap()).unwrap();
+        let required = required_space(&mut archive, &sample_metadata().directories).unwrap();
+        assert_eq!(required, 10);
+
+        assert!(check_disk_space(dir.path(), required).is_ok());
+        assert!(check_disk_space(dir.path(), u64::MAX).is_err());
+    }
+
+    #[test]
+    fn zip_magic_is_checked() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        write_zip(&filename, &[("ElvUI/marker", "new")]);
+        assert!(check_zip_magic(&filename, Some("application/zip")).is_ok());
+        assert!(check_zip_magic(&filename, Some("text/html")).is_ok());
+
+        std::fs::write(&filename, "<html>Down for maintenance</html>").unwrap();
+        let err = check_zip_magic(&filename, Some("text/html; charset=utf-8")).unwrap_err();
+        assert!(matches!(err, Error::Extract { .. }));
+        assert!(err.to_string().contains("text/html"));
+        assert!(err.to_string().contains("<html>Down for maintenance"));
+    }
+
+    #[test]
+    fn open_archive_rejects_invalid_zip() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        std::fs::write(&filename, "<html>maintenance</html>").unwrap();
+
+        let err = open_archive(&filename).unwrap_err();
+        assert!(matches!(err, Error::Extract { .. }));
+        assert!(err.to_string().contains("is not a valid zip archive"));
+    }
+}
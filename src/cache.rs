@@ -0,0 +1,136 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::error::Result;
+use crate::metadata::{ElvuiMetadata, Flavor, Source};
+
+/// A metadata response saved to disk, with when it was fetched
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CacheEntry {
+    pub fetched_at: String,
+    pub metadata: ElvuiMetadata,
+    /// Validators from the response, sent back to revalidate the entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl CacheEntry {
+    /// An entry for `metadata` fetched just now
+    pub fn new(metadata: ElvuiMetadata) -> Self {
+        CacheEntry { fetched_at: CacheEntry::now(), metadata, etag: None, last_modified: None }
+    }
+
+    pub(crate) fn now() -> String {
+        humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+    }
+
+    /// Whether the entry was fetched less than `ttl` ago. Entries with an
+    /// unreadable or future timestamp are treated as stale.
+    pub fn is_fresh(&self, ttl: Duration) -> bool {
+        humantime::parse_rfc3339(&self.fetched_at)
+            .ok()
+            .and_then(|fetched_at| SystemTime::now().duration_since(fetched_at).ok())
+            .is_some_and(|age| age < ttl)
+    }
+}
+
+/// Keeps the last metadata response per addon, source and flavor in `dir`.
+#[derive(Debug)]
+pub struct MetadataCache {
+    dir: PathBuf,
+}
+
+impl MetadataCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        MetadataCache { dir: dir.into() }
+    }
+
+    fn path(&self, slug: &str, source: Source, flavor: Flavor) -> PathBuf {
+        let name = format!("metadata-{}-{:?}-{:?}.json", slug, source, flavor).to_lowercase();
+        self.dir.join(name)
+    }
+
+    /// The cached entry however old it is, or `None` when there is no
+    /// usable entry. A corrupt cache file counts as a miss.
+    pub fn load(&self, slug: &str, source: Source, flavor: Flavor) -> Option<CacheEntry> {
+        let path = self.path(slug, source, flavor);
+        let content = std::fs::read_to_string(&path).ok()?;
+
+        match serde_json::from_str(&content) {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                debug!("Ignoring corrupt cache {:?}: {}", &path, err);
+                None
+            }
+        }
+    }
+
+    /// Saves `entry`, replacing any previous one atomically.
+    pub fn store(&self, slug: &str, source: Source, flavor: Flavor, entry: &CacheEntry) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        let mut file = tempfile::NamedTempFile::new_in(&self.dir)?;
+        serde_json::to_writer(&mut file, entry).map_err(std::io::Error::from)?;
+        file.persist(self.path(slug, source, flavor)).map_err(|err| err.error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_metadata;
+    use tempfile::Builder;
+
+    #[test]
+    fn cache_round_trips_per_flavor() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let cache = MetadataCache::new(dir.path().join("cache"));
+        assert!(cache.load("elvui", Source::Tukui, Flavor::Retail).is_none());
+
+        cache.store("elvui", Source::Tukui, Flavor::Retail, &CacheEntry::new(sample_metadata())).unwrap();
+
+        let entry = cache.load("elvui", Source::Tukui, Flavor::Retail).unwrap();
+        assert_eq!(entry.metadata.version, "13.21");
+        assert!(cache.load("elvui", Source::Tukui, Flavor::Era).is_none());
+        assert!(cache.load("elvui", Source::Github, Flavor::Retail).is_none());
+        assert!(cache.load("windtools", Source::Tukui, Flavor::Retail).is_none());
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let mut entry = CacheEntry::new(sample_metadata());
+        assert!(entry.is_fresh(Duration::from_secs(3600)));
+        assert!(!entry.is_fresh(Duration::ZERO));
+
+        entry.fetched_at = "2023-06-01T12:00:00Z".to_string();
+        assert!(!entry.is_fresh(Duration::from_secs(3600)));
+
+        entry.fetched_at = "yesterday".to_string();
+        assert!(!entry.is_fresh(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn entries_without_validators_still_parse() {
+        let entry: CacheEntry = serde_json::from_str(&format!(
+            r#"{{"fetched_at": "2023-06-01T12:00:00Z", "metadata": {}}}"#,
+            serde_json::to_string(&sample_metadata()).unwrap(),
+        )).unwrap();
+        assert_eq!(entry.etag, None);
+        assert_eq!(entry.last_modified, None);
+    }
+
+    #[test]
+    fn corrupt_cache_is_a_miss() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let cache = MetadataCache::new(dir.path());
+        std::fs::write(dir.path().join("metadata-elvui-tukui-retail.json"), "{").unwrap();
+
+        assert!(cache.load("elvui", Source::Tukui, Flavor::Retail).is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use log::debug;
+use regex::Regex;
+use std::path::Path;
+use sysinfo::{ProcessRefreshKind, RefreshKind, System};
+
+use crate::metadata::Flavor;
+
+/// Detects the interface number of the WoW client that `addons_path`
+/// belongs to, e.g. `100107` for 10.1.7.
+///
+/// The install root's `.build.info` is tried first, then the
+/// `lastAddonVersion` the client records in `WTF/Config.wtf`. Returns `None`
+/// when `addons_path` isn't inside a standard install or neither file says.
+pub fn detect_client_interface(addons_path: &Path, flavor: Flavor) -> Option<u32> {
+    // <root>/<flavor dir>/Interface/AddOns
+    let flavor_dir = addons_path.parent()?.parent()?;
+    let root = flavor_dir.parent()?;
+
+    let build_info = root.join(".build.info");
+    if let Ok(content) = std::fs::read_to_string(&build_info) {
+        debug!("Using build info: {:?}", &build_info);
+        if let Some(interface) = parse_build_info(&content, flavor) {
+            return Some(interface);
+        }
+    }
+
+    let config = flavor_dir.join("WTF").join("Config.wtf");
+    let content = std::fs::read_to_string(&config).ok()?;
+    debug!("Using client config: {:?}", &config);
+    parse_config_wtf(&content)
+}
+
+/// A game client process that has the addon files open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunningClient {
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Lists the WoW client processes currently running, of any flavor.
+pub fn running_clients() -> Vec<RunningClient> {
+    let system = System::new_with_specifics(RefreshKind::new().with_processes(ProcessRefreshKind::new()));
+
+    let mut clients: Vec<RunningClient> = system
+        .processes()
+        .iter()
+        .filter(|(_, process)| is_client_process(process.name()))
+        .map(|(pid, process)| RunningClient { pid: pid.as_u32(), name: process.name().to_string() })
+        .collect();
+    clients.sort_by_key(|client| client.pid);
+    clients
+}
+
+/// `Wow.exe`, `WowClassic.exe` and the PTR/beta builds on Windows (or under
+/// Wine), and `World of Warcraft` on macOS.
+fn is_client_process(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_suffix(".exe").unwrap_or(&name);
+
+    matches!(name, "wow" | "wowclassic" | "wowb" | "wowt" | "wow-64") || name.starts_with("world of warcraft")
+}
+
+/// Finds the version of `flavor` in a `.build.info` table, whose header
+/// row names each `|`-separated column as `Name!TYPE:size`.
+pub fn parse_build_info(content: &str, flavor: Flavor) -> Option<u32> {
+    let mut lines = content.lines();
+    let header: Vec<&str> = lines
+        .next()?
+        .split('|')
+        .map(|column| column.split('!').next().unwrap_or(column))
+        .collect();
+    let column = |name: &str| header.iter().position(|column| *column == name);
+    let (product, version) = (column("Product")?, column("Version")?);
+
+    lines
+        .map(|line| line.split('|').collect::<Vec<_>>())
+        .find(|fields| fields.get(product) == Some(&flavor.product()))
+        .and_then(|fields| interface_from_version(fields.get(version)?))
+}
+
+pub fn parse_config_wtf(content: &str) -> Option<u32> {
+    let re = Regex::new(r#"(?m)^SET lastAddonVersion "(?P<interface>\d+)""#).unwrap();
+    re.captures(content)?["interface"].parse().ok()
+}
+
+/// Turns a client version like `10.1.7.51237` into its interface number.
+fn interface_from_version(version: &str) -> Option<u32> {
+    let mut parts = version.split('.').map(|part| part.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let patch = parts.next()??;
+
+    Some(major * 10000 + minor * 100 + patch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    const BUILD_INFO: &str = "\
+Branch!STRING:0|Active!DEC:1|Build Key!HEX:16|Version!STRING:0|Product!STRING:0
+us|1|abc|10.1.7.51237|wow
+us|1|def|3.4.3.51505|wow_classic
+us|1|123|1.14.4.51146|wow_classic_era
+";
+
+    #[test]
+    fn build_info_version_per_flavor() {
+        assert_eq!(parse_build_info(BUILD_INFO, Flavor::Retail), Some(100107));
+        assert_eq!(parse_build_info(BUILD_INFO, Flavor::Wrath), Some(30403));
+        assert_eq!(parse_build_info(BUILD_INFO, Flavor::Era), Some(11404));
+        assert_eq!(parse_build_info("Branch!STRING:0\nus\n", Flavor::Retail), None);
+    }
+
+    #[test]
+    fn config_wtf_last_addon_version() {
+        let config = "SET locale \"enUS\"\nSET lastAddonVersion \"100107\"\n";
+        assert_eq!(parse_config_wtf(config), Some(100107));
+        assert_eq!(parse_config_wtf("SET locale \"enUS\"\n"), None);
+    }
+
+    #[test]
+    fn recognizes_client_processes() {
+        for name in ["Wow.exe", "WowClassic.exe", "WowT.exe", "World of Warcraft", "World of Warcraft Classic"] {
+            assert!(is_client_process(name), "{}", name);
+        }
+        for name in ["WowUp.exe", "Battle.net.exe", "elvui-manager"] {
+            assert!(!is_client_process(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn detects_interface_from_install_layout() {
+        let root = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let addons_path = root.path().join("_retail_/Interface/AddOns");
+        std::fs::create_dir_all(&addons_path).unwrap();
+        assert_eq!(detect_client_interface(&addons_path, Flavor::Retail), None);
+
+        std::fs::create_dir(root.path().join("_retail_/WTF")).unwrap();
+        std::fs::write(root.path().join("_retail_/WTF/Config.wtf"), "SET lastAddonVersion \"100105\"\n").unwrap();
+        assert_eq!(detect_client_interface(&addons_path, Flavor::Retail), Some(100105));
+
+        std::fs::write(root.path().join(".build.info"), BUILD_INFO).unwrap();
+        assert_eq!(detect_client_interface(&addons_path, Flavor::Retail), Some(100107));
+    }
+}
@@ -0,0 +1,1332 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn};
+use reqwest::blocking::Client;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache::{CacheEntry, MetadataCache};
+use crate::client::{detect_client_interface, running_clients};
+use crate::debug_dump::record_addon;
+use crate::error::{Error, Result};
+use crate::hooks::{post_webhook, run_hook, webhook_message, WebhookUrl};
+use crate::http::download_progress;
+use crate::install::{
+    backup_path, find_backups, orphaned_directories, plan_install, prepare_install, remove_stores, rollback_directories,
+    rollback_links, store_path, uninstall_directories, InstallOptions, Progress, ProgressEvent,
+};
+use crate::interrupt;
+use crate::manifest::{read_manifest, remove_manifest, write_manifest, Manifest};
+use crate::metadata::{
+    changelog_since, fetch_changelog, fetch_github_release, fetch_metadata, revalidate_metadata, trim_changelog, ElvuiMetadata,
+    Flavor, Source, ELVUI_SLUG,
+};
+use crate::notify::notify_installed;
+use crate::toc::{fetch_addon_toc, fetch_addon_version, fetch_installed_version};
+use crate::verify::{compare_with_hashes, DirectoryDiff};
+use crate::version::{compare_versions, is_outdated, same_version};
+
+/// How much of the changelog to show before cutting it off
+const CHANGELOG_LINES: usize = 30;
+
+const SPINNER_TICK: Duration = Duration::from_millis(100);
+
+/// Held while an addon's directories are swapped into place
+static SWAP_LOCK: Mutex<()> = Mutex::new(());
+
+/// The settings a command runs with, once flags and the config file have
+/// been resolved. Addons may be handled concurrently with the same options.
+#[derive(Debug)]
+pub struct RunOptions {
+    pub flavor: Flavor,
+    pub source: Source,
+    /// Tried before the built-in tukui API URL
+    pub api_url: Option<String>,
+    pub retries: u32,
+    /// Use cached metadata only and install nothing
+    pub offline: bool,
+    /// Fetch fresh metadata even when the cached copy is recent
+    pub refresh: bool,
+    /// Neither read nor update the cache, except for `status` falling back
+    /// to cached metadata
+    pub no_cache: bool,
+    /// Where metadata and downloads are cached, see `paths::default_cache_dir`
+    pub cache_dir: Option<PathBuf>,
+    /// How long cached metadata is reused before asking the API again
+    pub cache_ttl: Duration,
+    /// The addon directories to install instead of the ones the metadata lists
+    pub directories: Vec<String>,
+    /// Addon directories to never replace or remove
+    pub skip_dirs: Vec<String>,
+    /// Stay on this version instead of the latest one
+    pub pin: Option<String>,
+    /// Reinstall even when up to date, and change directories while WoW runs
+    pub force: bool,
+    /// Install from this local zip instead of downloading the release
+    pub archive: Option<PathBuf>,
+    /// Let a pin older than the installed version replace it
+    pub allow_downgrade: bool,
+    /// Remove directories an earlier release installed that the current
+    /// one no longer ships
+    pub prune: bool,
+    /// Fetch and show the changelog, even when no update is needed
+    pub changelog: bool,
+    /// Only show the changelog entries newer than this version, by default
+    /// the installed one
+    pub since: Option<String>,
+    /// Warn when the latest release is older than this
+    pub warn_stale: Option<Duration>,
+    pub backup: bool,
+    /// See `InstallOptions::overlay`
+    pub overlay: bool,
+    /// See `InstallOptions::link`
+    pub link: bool,
+    /// See `InstallOptions::checksums`
+    pub checksums: bool,
+    /// See `InstallOptions::best_effort`
+    pub best_effort: bool,
+    pub keep_temp: bool,
+    pub temp_dir: Option<PathBuf>,
+    pub sha256: Option<String>,
+    pub mirrors: Vec<String>,
+    /// Cap each download at this many bytes per second
+    pub max_rate: Option<u64>,
+    /// Log what would change without touching disk
+    pub dry_run: bool,
+    /// Shell command run before the new directories are swapped in, see
+    /// `hooks::run_hook`
+    pub pre_hook: Option<String>,
+    /// Shell command run after a successful install
+    pub post_hook: Option<String>,
+    pub webhook: Option<WebhookUrl>,
+    /// Show a desktop notification for each install
+    pub notify: bool,
+    /// Fail instead of warning when the addon's Interface is older than the client's
+    pub strict: bool,
+    /// Hide the download progress bar
+    pub quiet: bool,
+    /// Show a spinner on stderr while metadata is fetched
+    pub spinner: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Installed,
+    UpToDate,
+    None,
+}
+
+impl Action {
+    /// The name JSON output uses too
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Installed => "installed",
+            Action::UpToDate => "up_to_date",
+            Action::None => "none",
+        }
+    }
+}
+
+/// The outcome of `update`
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub addon: String,
+    pub installed_version: Option<String>,
+    pub latest_version: String,
+    /// The version installed, or that would be: the pin, the archive's or
+    /// the latest
+    pub target_version: String,
+    pub action: Action,
+    pub directories_updated: Vec<String>,
+    pub directories_pruned: Vec<String>,
+    /// One line describing the outcome
+    pub summary: String,
+    pub elapsed_secs: f64,
+}
+
+/// Whether an update is available, as `check` finds out
+#[derive(Serialize, Debug)]
+pub struct UpdateCheck {
+    pub addon: String,
+    #[serde(skip)]
+    pub name: String,
+    pub installed_version: Option<String>,
+    pub latest_version: String,
+    pub update_available: bool,
+}
+
+/// Everything `status` knows about an addon. Its JSON is what
+/// `status --format json` prints.
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct Status {
+    pub addon: String,
+    pub name: String,
+    pub installed_version: Option<String>,
+    /// `None` when there is neither a response nor cached metadata
+    pub latest_version: Option<String>,
+    pub update_available: Option<bool>,
+    /// When the metadata was fetched, if it came from the cache
+    pub metadata_cached_at: Option<String>,
+    pub installed_at: Option<String>,
+    pub pinned: Option<String>,
+    pub directories: Vec<String>,
+    /// Directories a best-effort install couldn't replace, still on an
+    /// earlier release than the one installed
+    pub stale_directories: Vec<String>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+
+        let installed = self.installed_version.as_deref().unwrap_or("not installed");
+        match (&self.installed_at, &self.pinned) {
+            (Some(at), Some(pin)) => writeln!(f, "  Installed:   {} (on {}, pinned to {})", installed, at, pin)?,
+            (Some(at), None) => writeln!(f, "  Installed:   {} (on {})", installed, at)?,
+            (None, _) => writeln!(f, "  Installed:   {}", installed)?,
+        }
+
+        let latest = self.latest_version.as_deref().unwrap_or("unknown");
+        match &self.metadata_cached_at {
+            Some(at) => writeln!(f, "  Latest:      {} (cached {})", latest, at)?,
+            None => writeln!(f, "  Latest:      {}", latest)?,
+        }
+
+        let state = match self.update_available {
+            Some(true) => "update available",
+            Some(false) => "up to date",
+            None => "unknown",
+        };
+        writeln!(f, "  Status:      {}", state)?;
+        write!(f, "  Directories: {}", self.directories.join(", "))?;
+        if !self.stale_directories.is_empty() {
+            write!(f, "\n  Stale:       {} (update to replace)", self.stale_directories.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The installed and latest version of an addon as `compare` reports them
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    pub addon: String,
+    pub installed_version: Option<String>,
+    pub latest_version: String,
+    /// `Lt`, `Eq` or `Gt` from comparing the installed version with the
+    /// latest, or why they couldn't be compared
+    pub result: String,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let installed = self.installed_version.as_deref().map_or("not installed".to_string(), |version| format!("{:?}", version));
+        write!(f, "{}: installed {} vs latest {:?}: {}", self.addon, installed, self.latest_version, self.result)
+    }
+}
+
+/// Installs or updates `slug` in `addons_path` when it is out of date,
+/// pruning and checking the result as `options` ask. `show_changelog` is
+/// given the changelog when `options.changelog` is set.
+pub fn update(
+    options: &RunOptions,
+    client: &Client,
+    addons_path: &Path,
+    slug: &str,
+    show_changelog: impl Fn(&str),
+) -> Result<Report> {
+    let started = Instant::now();
+    let mut install_needed = true;
+
+    // Check latest available
+    let mut metadata = fetch_latest(options, client, slug)?;
+    if !options.directories.is_empty() {
+        debug!("Overriding the directories of {}: {:?}", metadata.name, metadata.directories);
+        metadata.directories = options.directories.clone();
+    }
+    let latest_version = metadata.version.clone();
+    info!(
+        "Found latest available {} version: {}{} (updated on {})",
+        metadata.name,
+        latest_version,
+        if metadata.prerelease { ", a prerelease" } else { "" },
+        metadata.last_update
+    );
+    if let Some(threshold) = options.warn_stale {
+        warn_if_stale(&metadata, threshold);
+    }
+
+    // Check installed version
+    let installed_version = installed_version(addons_path, &metadata, options.flavor);
+
+    let manifest = read_manifest(addons_path, slug).unwrap_or_else(|err| {
+        warn!("Ignoring unreadable manifest: {}", describe(&err));
+        None
+    });
+    record_addon(slug, |addon| {
+        addon.metadata = Some(metadata.clone());
+        addon.installed_version = installed_version.clone();
+        addon.manifest = manifest.clone();
+    });
+    let pin = match &options.pin {
+        Some(pin) => Some(pin.clone()),
+        None if options.force => None,
+        None => manifest.as_ref().and_then(|manifest| manifest.pinned.clone()),
+    };
+    let mut target_version = pin.clone().unwrap_or_else(|| latest_version.clone());
+    if let Some(pin) = &pin {
+        info!("Pinned to {} {}", metadata.name, pin);
+    }
+
+    if let Some(archive) = &options.archive {
+        info!("Installing from {}, skipping version check", archive.display());
+    } else if options.force {
+        info!("Forcing install, skipping version check");
+    } else if let Some(installed_version) = &installed_version {
+        install_needed = is_outdated(installed_version, &target_version)?;
+        if is_outdated(&target_version, installed_version)? {
+            if options.allow_downgrade {
+                info!("Downgrading {} from {} to {}", metadata.name, installed_version, target_version);
+                install_needed = true;
+            } else if pin.is_some() {
+                warn!(
+                    "Installed {} {} is newer than the pinned {}, pass --allow-downgrade to replace it",
+                    metadata.name, installed_version, target_version
+                );
+            } else {
+                warn!(
+                    "Installed {} {} is newer than the latest available {}, the API may be stale",
+                    metadata.name, installed_version, target_version
+                );
+            }
+        }
+        debug!("After compare, install_needed = {}", install_needed);
+    }
+    let stale = manifest
+        .as_ref()
+        .filter(|manifest| !install_needed && !manifest.stale.is_empty() && same_version(&manifest.version, &target_version));
+    if let Some(manifest) = stale {
+        info!(
+            "{} left at an earlier release by the last install, reinstalling {} {}",
+            manifest.stale.join(", "), metadata.name, target_version
+        );
+        install_needed = true;
+    }
+
+    if install_needed && !same_version(&target_version, &latest_version) && !options.offline && options.archive.is_none() {
+        if slug != ELVUI_SLUG {
+            return Err(Error::install(format!(
+                "Unable to install {} {}! The tukui API only serves the latest version ({}).",
+                metadata.name, target_version, latest_version
+            )));
+        }
+        if !options.source.is_github() {
+            return Err(Error::install(format!(
+                "Unable to install {} {}! The tukui API only serves the latest version ({}), pass --source github for older releases.",
+                metadata.name, target_version, latest_version
+            )));
+        }
+        metadata = fetch_github_release(client, &target_version, options.retries)?;
+        record_addon(slug, |addon| addon.metadata = Some(metadata.clone()));
+    }
+
+    let recorded = manifest.map(|manifest| manifest.directories).unwrap_or_default();
+    let orphans = without_skipped(&options.skip_dirs, &orphaned_directories(addons_path, &recorded, &metadata.directories));
+    if options.prune && !orphans.is_empty() {
+        info!("Pruning directories {} no longer ships:", metadata.name);
+        for target in &orphans {
+            info!("  {}", target);
+        }
+    } else {
+        for target in &orphans {
+            info!("{} is no longer part of {}, pass --prune to remove it", target, metadata.name);
+        }
+    }
+
+    let mut report = Report {
+        addon: slug.to_string(),
+        installed_version,
+        latest_version,
+        target_version: target_version.clone(),
+        action: Action::UpToDate,
+        directories_updated: Vec::new(),
+        directories_pruned: Vec::new(),
+        summary: String::new(),
+        elapsed_secs: 0.0,
+    };
+
+    if install_needed {
+        info!("Changelog: {}", metadata.changelog_url);
+    }
+    if options.changelog {
+        let changelog = fetch_changelog(client, &metadata.changelog_url, options.retries)
+            .map_err(|err| Error::install_context("could not fetch the changelog", err))?;
+        let since = options.since.as_ref().or(report.installed_version.as_ref());
+        let changelog = since.and_then(|since| changelog_since(&changelog, since)).unwrap_or(changelog);
+        show_changelog(&trim_changelog(&changelog, CHANGELOG_LINES));
+    }
+
+    if install_needed && options.offline && options.archive.is_none() {
+        info!("Offline, not installing {} {}", metadata.name, target_version);
+        report.action = Action::None;
+        check_interface(options, addons_path, &metadata)?;
+        report.elapsed_secs = started.elapsed().as_secs_f64();
+        report.summary = summary_line(&report, &metadata.name, &target_version, "offline");
+        return Ok(report);
+    }
+
+    if install_needed {
+        let mut install_metadata = metadata.clone();
+        install_metadata.directories = without_skipped(&options.skip_dirs, &metadata.directories);
+        if install_metadata.directories.is_empty() {
+            return Err(Error::install(format!(
+                "Every directory of {} is in --skip-dirs, nothing to install!",
+                metadata.name
+            )));
+        }
+
+        if let Some(archive) = &options.archive {
+            info!("Installing {} from {}", metadata.name, archive.display());
+        } else if metadata.prerelease {
+            warn!("Installing {} {}, a prerelease that may be unstable", metadata.name, metadata.version);
+        } else {
+            info!("Installing {} {}", metadata.name, metadata.version);
+        }
+        let install_options = InstallOptions {
+            pin,
+            retain: if options.prune { Vec::new() } else { orphans.clone() },
+            checksums: options.checksums,
+            link: options.link,
+            ..install_options(options)
+        };
+
+        if options.dry_run {
+            if let Some(hook) = &options.pre_hook {
+                info!("[dry-run] Would run pre-hook `{}`", hook);
+            }
+            for operation in plan_install(addons_path, &install_metadata, &install_options)? {
+                info!("[dry-run] Would {}", operation);
+            }
+            if let Some(hook) = &options.post_hook {
+                info!("[dry-run] Would run post-hook `{}`", hook);
+            }
+            report.action = Action::None;
+        } else {
+            let directories = install_metadata.directories.clone();
+            let mut prepared = prepare_install(client, addons_path, install_metadata, &install_options)?;
+            record_addon(slug, |addon| addon.archive_entries = prepared.archive_entries().to_vec());
+            if install_options.keep_temp {
+                info!("Keeping temporary files in {}", prepared.temp_path().display());
+            }
+            if install_options.archive.is_some() {
+                match prepared.use_release_version(options.flavor) {
+                    Ok(version) => target_version = version.to_string(),
+                    Err(err) => warn!(
+                        "Unable to read the version from the archive, recording {}: {}",
+                        target_version,
+                        describe(&err)
+                    ),
+                }
+            } else if prepared.reused_archive() {
+                info!("Reusing {}, downloaded by an earlier run", prepared.downloaded_from());
+            } else if prepared.downloaded_from() != metadata.url {
+                warn!("Downloading {} failed, used mirror {}", metadata.url, prepared.downloaded_from());
+            }
+            for file in prepared.unshipped_lua_files() {
+                if install_options.backup {
+                    warn!("{} is not part of the new release, it will only be kept in the backup", file.display());
+                } else {
+                    warn!("{} is not part of the new release and will be deleted", file.display());
+                }
+            }
+            // Only one addon at a time may swap directories in `addons_path`
+            let _guard = SWAP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+            interrupt::check()?;
+            if let Some(hook) = &options.pre_hook {
+                run_hook(hook, &metadata, addons_path)
+                    .map_err(|err| Error::install_context("Pre-hook failed, not installing", err))?;
+            }
+            check_client_not_running(options.force)?;
+            match prepared.commit() {
+                Ok(()) => {}
+                Err(Error::PartialInstall { failed }) => {
+                    for (target, err) in &failed {
+                        warn!("Unable to update {}: {}", target, err);
+                    }
+                    return Err(Error::install(format!(
+                        "{} of {} directories could not be updated, the others are on {} {}. Run `verify --fix` to retry them.",
+                        failed.len(),
+                        directories.len(),
+                        metadata.name,
+                        target_version
+                    )));
+                }
+                Err(err) => return Err(err),
+            }
+            if let Some(hook) = &options.post_hook {
+                if let Err(err) = run_hook(hook, &metadata, addons_path) {
+                    warn!("Post-hook failed: {}", describe(&err));
+                }
+            }
+            if options.notify {
+                notify_installed(&metadata.name, &target_version);
+            }
+            if let Some(webhook) = &options.webhook {
+                let message = webhook_message(&metadata, report.installed_version.as_deref(), &target_version);
+                if let Err(err) = post_webhook(client, webhook, &message) {
+                    warn!("Webhook failed: {}", describe(&err));
+                }
+            }
+            report.action = Action::Installed;
+            report.directories_updated = directories;
+        }
+    }
+
+    if options.prune && !orphans.is_empty() {
+        if options.dry_run {
+            for target in &orphans {
+                info!("[dry-run] Would remove {}", addons_path.join(target).display());
+            }
+        } else {
+            let _guard = SWAP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+            if report.action != Action::Installed {
+                check_client_not_running(options.force)?;
+            }
+            report.directories_pruned = prune(addons_path, slug, &orphans)?;
+        }
+    }
+
+    check_interface(options, addons_path, &metadata)?;
+
+    report.elapsed_secs = started.elapsed().as_secs_f64();
+    report.summary = summary_line(&report, &metadata.name, &target_version, "dry run");
+    Ok(report)
+}
+
+/// The install options `update` and `verify` share.
+fn install_options(options: &RunOptions) -> InstallOptions {
+    InstallOptions {
+        backup: options.backup,
+        quiet: options.quiet,
+        max_rate: options.max_rate,
+        keep_temp: options.keep_temp,
+        archive: options.archive.clone(),
+        download_cache: download_cache_dir(options),
+        temp_dir: options.temp_dir.clone(),
+        sha256: options.sha256.clone(),
+        retries: options.retries,
+        mirrors: options.mirrors.clone(),
+        best_effort: options.best_effort,
+        overlay: options.overlay,
+        progress: download_bar(options.quiet),
+        ..Default::default()
+    }
+}
+
+/// The installed version of the addon, or `None` when it has to be
+/// installed fresh, logging which case it is and why.
+pub fn installed_version(addons_path: &Path, metadata: &ElvuiMetadata, flavor: Flavor) -> Option<String> {
+    let main_directory = addons_path.join(metadata.main_directory());
+    match fetch_addon_version(addons_path, metadata, flavor) {
+        Ok(installed_version) => {
+            info!("Found installed {} version: {}", metadata.name, installed_version);
+            Some(installed_version)
+        }
+        Err(Error::TocNotFound { source, .. }) if source.kind() == std::io::ErrorKind::NotFound && !main_directory.exists() => {
+            info!(
+                "{} is not currently installed in {}, performing fresh install",
+                metadata.name,
+                addons_path.display()
+            );
+            None
+        }
+        Err(err) => {
+            warn!(
+                "Unable to read the installed {} version, reinstalling it: {}",
+                metadata.name,
+                describe(&err)
+            );
+            None
+        }
+    }
+}
+
+/// Removes `orphans` and drops them from the addon's manifest, returning
+/// the directories removed.
+fn prune(addons_path: &Path, slug: &str, orphans: &[String]) -> Result<Vec<String>> {
+    let removed = uninstall_directories(addons_path, orphans)?;
+    for target in &removed {
+        info!("Removed {}", target);
+    }
+
+    if let Some(mut manifest) = read_manifest(addons_path, slug)? {
+        manifest.directories.retain(|target| !orphans.contains(target));
+        write_manifest(addons_path, slug, &manifest)?;
+    }
+
+    Ok(removed)
+}
+
+/// E.g. "Updated ElvUI 13.20 → 13.21 (3 directories) in 2.1s". `version` is
+/// the one installed, or that would have been, and `reason` says why an
+/// install was skipped.
+fn summary_line(report: &Report, name: &str, version: &str, reason: &str) -> String {
+    let mut outcome = match (report.action, &report.installed_version) {
+        (Action::Installed, Some(installed)) => format!(
+            "Updated {} {} → {} ({} directories)",
+            name, installed, version, report.directories_updated.len()
+        ),
+        (Action::Installed, None) => format!(
+            "Installed {} {} ({} directories)",
+            name, version, report.directories_updated.len()
+        ),
+        (Action::UpToDate, installed) => format!(
+            "{} already up to date ({})",
+            name, installed.as_deref().unwrap_or(version)
+        ),
+        (Action::None, _) => format!("{} {} not installed ({})", name, version, reason),
+    };
+
+    if !report.directories_pruned.is_empty() {
+        outcome.push_str(&format!(", pruned {} directories", report.directories_pruned.len()));
+    }
+
+    format!("{} in {:.1}s", outcome, report.elapsed_secs)
+}
+
+/// Refuses to change addon directories while a WoW client has them open,
+/// which corrupts them and loses settings when the game exits, unless
+/// `force` is set.
+fn check_client_not_running(force: bool) -> Result<()> {
+    let running = running_clients();
+    if running.is_empty() {
+        return Ok(());
+    }
+
+    let processes: Vec<String> = running.iter().map(|client| format!("{} (pid {})", client.name, client.pid)).collect();
+    if force {
+        warn!("World of Warcraft is running: {}. Continuing because of --force", processes.join(", "));
+        return Ok(());
+    }
+
+    Err(Error::install(format!(
+        "World of Warcraft is running: {}! Close it first, or pass --force to continue anyway.",
+        processes.join(", ")
+    )))
+}
+
+/// `directories` without the ones in `skip_dirs`, ignoring case.
+fn without_skipped(skip_dirs: &[String], directories: &[String]) -> Vec<String> {
+    directories
+        .iter()
+        .filter(|target| {
+            let skipped = skip_dirs.iter().any(|skip| skip.eq_ignore_ascii_case(target));
+            if skipped {
+                info!("Skipping {}, it is in --skip-dirs", target);
+            }
+            !skipped
+        })
+        .cloned()
+        .collect()
+}
+
+/// Warns when every Interface the installed addon declares is older than
+/// the one the WoW client runs, which makes the game flag it as out of date. With
+/// `options.strict` this is an error instead.
+fn check_interface(options: &RunOptions, addons_path: &Path, metadata: &ElvuiMetadata) -> Result<()> {
+    let client_interface = match detect_client_interface(addons_path, options.flavor) {
+        Some(interface) => interface,
+        None => {
+            debug!("Unable to detect the WoW client's interface version");
+            return Ok(());
+        }
+    };
+    let toc = match fetch_addon_toc(addons_path, metadata, options.flavor) {
+        Ok(toc) => toc,
+        Err(_) => return Ok(()),
+    };
+    debug!("{} interfaces: {:?}, client interface: {}", metadata.name, toc.interfaces, client_interface);
+
+    if !toc.supports_interface(client_interface) {
+        let interfaces: Vec<String> = toc.interfaces.iter().map(u32::to_string).collect();
+        let message = format!(
+            "{} was made for interface {} but the WoW client is on {}, so the game may list it as out of date",
+            metadata.name,
+            interfaces.join(", "),
+            client_interface
+        );
+        if options.strict {
+            return Err(Error::install(format!("{}!", message)));
+        }
+        warn!("{}", message);
+    }
+
+    Ok(())
+}
+
+/// Warns when the latest release of `metadata` is older than `threshold`,
+/// e.g. when the API is still serving data from before a major patch.
+fn warn_if_stale(metadata: &ElvuiMetadata, threshold: Duration) {
+    let Some(updated) = metadata.last_updated() else {
+        warn!("Unable to tell how old {} {} is from `{}`", metadata.name, metadata.version, metadata.last_update);
+        return;
+    };
+    let age = SystemTime::now().duration_since(updated).unwrap_or_default();
+    if age > threshold {
+        warn!(
+            "The latest {} {} was last updated {} days ago (on {}), the API data may be stale",
+            metadata.name,
+            metadata.version,
+            age.as_secs() / (24 * 60 * 60),
+            metadata.last_update
+        );
+    }
+}
+
+/// Fetches the latest metadata, reusing the cached response while it is
+/// younger than the cache TTL and revalidating it with the API after that.
+/// Offline only the cache is used, however old it is.
+pub fn fetch_latest(options: &RunOptions, client: &Client, slug: &str) -> Result<ElvuiMetadata> {
+    if options.offline {
+        let entry = cached_metadata(options, slug)
+            .ok_or_else(|| Error::install("No cached metadata to use offline! Run once without --offline first."))?;
+        debug!("Offline, using metadata cached at {}", entry.fetched_at);
+        return Ok(entry.metadata);
+    }
+
+    let cache = match &options.cache_dir {
+        Some(dir) if !options.no_cache => MetadataCache::new(dir.clone()),
+        _ => {
+            let spinner = metadata_spinner(options);
+            let metadata =
+                fetch_metadata(client, slug, options.source, options.flavor, options.api_url.as_deref(), options.retries);
+            spinner.finish_and_clear();
+            return metadata;
+        }
+    };
+
+    let cached = match cache.load(slug, options.source, options.flavor) {
+        Some(entry) if !options.refresh && entry.is_fresh(options.cache_ttl) => {
+            debug!("Using metadata cached at {}", entry.fetched_at);
+            return Ok(entry.metadata);
+        }
+        cached => cached,
+    };
+
+    let spinner = metadata_spinner(options);
+    let entry = revalidate_metadata(
+        client,
+        slug,
+        options.source,
+        options.flavor,
+        options.api_url.as_deref(),
+        options.retries,
+        cached,
+    );
+    spinner.finish_and_clear();
+    let entry = entry?;
+    if let Err(err) = cache.store(slug, options.source, options.flavor, &entry) {
+        warn!("Unable to cache metadata: {}", describe(&err));
+    }
+
+    Ok(entry.metadata)
+}
+
+/// The cached metadata for `slug`, however old it is.
+pub fn cached_metadata(options: &RunOptions, slug: &str) -> Option<CacheEntry> {
+    let dir = options.cache_dir.clone()?;
+    MetadataCache::new(dir).load(slug, options.source, options.flavor)
+}
+
+/// A "Checking for updates…" spinner shown on stderr while metadata is
+/// fetched, unless `options.spinner` is off.
+fn metadata_spinner(options: &RunOptions) -> ProgressBar {
+    if !options.spinner {
+        return ProgressBar::hidden();
+    }
+
+    let spinner = ProgressBar::new_spinner()
+        .with_style(ProgressStyle::with_template("{spinner} {msg}").unwrap())
+        .with_message("Checking for updates…");
+    spinner.enable_steady_tick(SPINNER_TICK);
+    spinner
+}
+
+/// Draws the download of an install as a progress bar, started afresh for
+/// every attempt and cleared once extraction begins.
+fn download_bar(quiet: bool) -> Option<Progress> {
+    if quiet {
+        return None;
+    }
+
+    let bar: Mutex<Option<ProgressBar>> = Mutex::new(None);
+    Some(Progress::new(move |event| {
+        let mut bar = bar.lock().unwrap();
+        match event {
+            ProgressEvent::DownloadStarted { total, .. } => {
+                if let Some(previous) = bar.replace(download_progress(total, false)) {
+                    previous.finish_and_clear();
+                }
+            }
+            ProgressEvent::DownloadProgress { bytes } => {
+                if let Some(bar) = bar.as_ref() {
+                    bar.set_position(bytes);
+                }
+            }
+            ProgressEvent::Extracting | ProgressEvent::SwappingDir { .. } | ProgressEvent::Done => {
+                if let Some(bar) = bar.take() {
+                    bar.finish_and_clear();
+                }
+            }
+        }
+    }))
+}
+
+/// Where downloaded archives are kept until installed, so a run that fails
+/// after downloading doesn't download again
+fn download_cache_dir(options: &RunOptions) -> Option<PathBuf> {
+    options.cache_dir.as_ref().filter(|_| !options.no_cache).map(|dir| dir.join("archives"))
+}
+
+/// Finds out whether an update is available without changing anything.
+/// An addon that isn't installed counts as out of date.
+pub fn check(options: &RunOptions, client: &Client, addons_path: &Path, slug: &str) -> Result<UpdateCheck> {
+    let metadata = fetch_latest(options, client, slug)?;
+    let installed_version = fetch_addon_version(addons_path, &metadata, options.flavor).ok();
+    record_addon(slug, |addon| {
+        addon.metadata = Some(metadata.clone());
+        addon.installed_version = installed_version.clone();
+    });
+    if let Some(threshold) = options.warn_stale {
+        warn_if_stale(&metadata, threshold);
+    }
+
+    let update_available = match &installed_version {
+        Some(installed_version) => is_outdated(installed_version, &metadata.version)?,
+        None => true,
+    };
+
+    Ok(UpdateCheck {
+        addon: slug.to_string(),
+        name: metadata.name,
+        installed_version,
+        latest_version: metadata.version,
+        update_available,
+    })
+}
+
+pub fn compare(options: &RunOptions, client: &Client, addons_path: &Path, slug: &str) -> Result<Comparison> {
+    let metadata = fetch_latest(options, client, slug)?;
+    let installed_version = fetch_addon_version(addons_path, &metadata, options.flavor).ok();
+    let result = match &installed_version {
+        Some(installed_version) => comparison_result(installed_version, &metadata.version),
+        None => "-".to_string(),
+    };
+
+    Ok(Comparison { addon: metadata.name, installed_version, latest_version: metadata.version, result })
+}
+
+fn comparison_result(installed_version: &str, latest_version: &str) -> String {
+    match compare_versions(installed_version, latest_version) {
+        Ok(std::cmp::Ordering::Less) => "Lt".to_string(),
+        Ok(std::cmp::Ordering::Equal) => "Eq".to_string(),
+        Ok(std::cmp::Ordering::Greater) => "Gt".to_string(),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+/// Assembles the status of `slug`, so it never fails: whatever can't be
+/// found out, such as the latest version without a network or a cache, is
+/// left out.
+pub fn status(options: &RunOptions, client: &Client, addons_path: &Path, slug: &str) -> Status {
+    let fetched = if options.offline {
+        None
+    } else {
+        match fetch_latest(options, client, slug) {
+            Ok(metadata) => Some(metadata),
+            Err(err) => {
+                warn!("Unable to fetch the latest {} metadata: {}", slug, describe(&err));
+                None
+            }
+        }
+    };
+    let (metadata, metadata_cached_at) = match fetched {
+        Some(metadata) => (Some(metadata), None),
+        None => match cached_metadata(options, slug) {
+            Some(entry) => (Some(entry.metadata), Some(entry.fetched_at)),
+            None => (None, None),
+        },
+    };
+
+    let installed_version = match &metadata {
+        Some(metadata) => fetch_addon_version(addons_path, metadata, options.flavor).ok(),
+        None if slug == ELVUI_SLUG => fetch_installed_version(addons_path, options.flavor).ok(),
+        None => None,
+    };
+    let manifest = read_manifest(addons_path, slug).unwrap_or_else(|err| {
+        warn!("Ignoring unreadable manifest: {}", describe(&err));
+        None
+    });
+    record_addon(slug, |addon| {
+        addon.metadata = metadata.clone();
+        addon.installed_version = installed_version.clone();
+        addon.manifest = manifest.clone();
+    });
+
+    let latest_version = metadata.as_ref().map(|metadata| metadata.version.clone());
+    let update_available = latest_version.as_ref().and_then(|latest| match &installed_version {
+        Some(installed) => match is_outdated(installed, latest) {
+            Ok(outdated) => Some(outdated),
+            Err(err) => {
+                warn!("Unable to compare versions: {}", describe(&err));
+                None
+            }
+        },
+        None => Some(true),
+    });
+    let directories = match (&manifest, &metadata) {
+        (Some(manifest), _) => manifest.directories.clone(),
+        (None, Some(metadata)) => metadata.directories.clone(),
+        (None, None) => Vec::new(),
+    };
+    let stale_directories = manifest.as_ref().map(|manifest| manifest.stale.clone()).unwrap_or_default();
+    // Stale directories still need the installed release
+    let update_available = update_available.map(|available| available || !stale_directories.is_empty());
+
+    Status {
+        addon: slug.to_string(),
+        name: metadata.map(|metadata| metadata.name).unwrap_or_else(|| slug.to_string()),
+        installed_version,
+        latest_version,
+        update_available,
+        metadata_cached_at,
+        installed_at: manifest.as_ref().map(|manifest| manifest.installed_at.clone()),
+        pinned: manifest.and_then(|manifest| manifest.pinned),
+        directories,
+        stale_directories,
+    }
+}
+
+/// Compares the installed files of `slug` with its release and hands the
+/// differences to `report`. With `fix`, directories with missing or
+/// modified files are reinstalled. Returns whether the install is intact
+/// afterwards.
+pub fn verify(
+    options: &RunOptions,
+    client: &Client,
+    addons_path: &Path,
+    slug: &str,
+    fix: bool,
+    mut report: impl FnMut(&ElvuiMetadata, &[DirectoryDiff]),
+) -> Result<bool> {
+    let mut metadata = fetch_latest(options, client, slug)?;
+    if !options.directories.is_empty() {
+        metadata.directories = options.directories.clone();
+    }
+    let installed_version = fetch_addon_version(addons_path, &metadata, options.flavor)
+        .map_err(|err| Error::install_context(format!("{} is not installed", metadata.name), err))?;
+    // A local archive is compared with whatever version it holds
+    if !same_version(&installed_version, &metadata.version) && options.archive.is_none() {
+        if slug != ELVUI_SLUG || !options.source.is_github() {
+            return Err(Error::install(format!(
+                "Unable to verify {} {}! The tukui API only serves the latest version ({}), update first or pass --source github.",
+                metadata.name, installed_version, metadata.version
+            )));
+        }
+        metadata = fetch_github_release(client, &installed_version, options.retries)?;
+    }
+    let manifest = read_manifest(addons_path, slug).unwrap_or_else(|err| {
+        warn!("Ignoring unreadable manifest: {}", describe(&err));
+        None
+    });
+
+    let mut install_metadata = metadata.clone();
+    install_metadata.directories = without_skipped(&options.skip_dirs, &metadata.directories);
+
+    // Checksums recorded at install time spare downloading the release,
+    // unless something needs to be reinstalled from it
+    let recorded = manifest
+        .as_ref()
+        .filter(|manifest| same_version(&manifest.version, &installed_version))
+        .and_then(|manifest| manifest.checksums.as_ref())
+        .filter(|_| options.archive.is_none())
+        .filter(|checksums| install_metadata.directories.iter().all(|target| checksums.contains_key(target)));
+    if let Some(recorded) = recorded {
+        info!("Verifying {} {} against the checksums recorded at install", metadata.name, metadata.version);
+        let diffs = compare_with_hashes(recorded, addons_path, &install_metadata.directories)?;
+        report(&metadata, &diffs);
+        let intact = diffs.iter().all(|diff| !diff.is_damaged());
+        if intact || !fix {
+            return Ok(intact);
+        }
+    }
+
+    if options.offline && options.archive.is_none() {
+        return Err(Error::install(
+            "verify downloads the release to compare with, so it can't be used with --offline unless checksums were recorded with --checksums!",
+        ));
+    }
+    info!("Verifying {} {} against its release", metadata.name, metadata.version);
+    let install_options = InstallOptions {
+        pin: manifest.as_ref().and_then(|manifest| manifest.pinned.clone()),
+        retain: manifest.as_ref().map(|manifest| manifest.directories.clone()).unwrap_or_default(),
+        // Keep recording checksums when they were before
+        checksums: options.checksums || manifest.as_ref().is_some_and(|manifest| manifest.checksums.is_some()),
+        // Reinstalled directories stay linked like the others
+        link: manifest.as_ref().is_some_and(|manifest| manifest.store.is_some()),
+        ..install_options(options)
+    };
+    let mut prepared = prepare_install(client, addons_path, install_metadata, &install_options)?;
+    let diffs = prepared.verify()?;
+    report(&metadata, &diffs);
+
+    let damaged: Vec<String> = diffs
+        .iter()
+        .filter(|diff| diff.is_damaged())
+        .map(|diff| diff.directory.clone())
+        .collect();
+    if damaged.is_empty() || !fix {
+        return Ok(damaged.is_empty());
+    }
+
+    if options.dry_run {
+        for target in &damaged {
+            info!("[dry-run] Would reinstall {}", addons_path.join(target).display());
+        }
+        return Ok(false);
+    }
+
+    prepared.restrict_to(&damaged);
+    for file in prepared.unshipped_lua_files() {
+        if install_options.backup {
+            warn!("{} is not part of the release, it will only be kept in the backup", file.display());
+        } else {
+            warn!("{} is not part of the release and will be deleted", file.display());
+        }
+    }
+    let _guard = SWAP_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    check_client_not_running(options.force)?;
+    prepared.commit()?;
+    info!("Reinstalled {}", damaged.join(", "));
+
+    Ok(true)
+}
+
+/// Restores the directories of `slug` from their backups, or for an
+/// install made with `--link`, links them to the release linked before.
+/// Nothing is changed unless `confirm` answers yes to its prompt.
+pub fn rollback(
+    options: &RunOptions,
+    client: &Client,
+    addons_path: &Path,
+    slug: &str,
+    confirm: impl FnOnce(&str) -> std::io::Result<bool>,
+) -> Result<()> {
+    let manifest = read_manifest(addons_path, slug)?;
+    // The first linked install has no earlier release in the store, but
+    // may have backed up the directories it replaced
+    if let Some(manifest) = manifest.filter(|manifest| manifest.previous_store.is_some()) {
+        return rollback_linked(options, addons_path, slug, &manifest, confirm);
+    }
+
+    let directories = without_skipped(&options.skip_dirs, &fetch_latest(options, client, slug)?.directories);
+    let restorable = find_backups(addons_path, &directories);
+    for target in &directories {
+        if !restorable.contains(target) {
+            warn!("No backup found for {}, leaving it as is", target);
+        }
+    }
+
+    if restorable.is_empty() {
+        return Err(Error::install(format!("No backups found in `{}`", addons_path.display())));
+    }
+
+    for target in &restorable {
+        info!("Restoring {} from backup", target);
+    }
+
+    if options.dry_run {
+        for target in &restorable {
+            info!(
+                "[dry-run] Would restore {} from {}",
+                addons_path.join(target).display(), backup_path(addons_path, target).display()
+            );
+        }
+        return Ok(());
+    }
+
+    check_client_not_running(options.force)?;
+    if !confirm("Replace the current directories with their backups?")? {
+        info!("Rollback cancelled");
+        return Ok(());
+    }
+
+    rollback_directories(addons_path, &restorable)?;
+    Ok(())
+}
+
+/// Rolls back an install made with `--link` by pointing its links at the
+/// release linked before.
+fn rollback_linked(
+    options: &RunOptions,
+    addons_path: &Path,
+    slug: &str,
+    manifest: &Manifest,
+    confirm: impl FnOnce(&str) -> std::io::Result<bool>,
+) -> Result<()> {
+    let previous = manifest.previous_store.as_deref().unwrap_or_default();
+    info!("Rolling back {} {} to {}", slug, manifest.version, previous);
+
+    if options.dry_run {
+        for target in &manifest.directories {
+            info!(
+                "[dry-run] Would link {} to {}",
+                addons_path.join(target).display(), store_path(addons_path, previous).join(target).display()
+            );
+        }
+        return Ok(());
+    }
+
+    check_client_not_running(options.force)?;
+    if !confirm("Link the directories to the earlier release?")? {
+        info!("Rollback cancelled");
+        return Ok(());
+    }
+
+    let manifest = rollback_links(addons_path, slug)?;
+    info!("Rolled back {} to {}", slug, manifest.version);
+    Ok(())
+}
+
+/// Removes the directories of `slug`, with its store and manifest, once
+/// `confirm` answers yes to its prompt.
+pub fn uninstall(
+    options: &RunOptions,
+    client: &Client,
+    addons_path: &Path,
+    slug: &str,
+    confirm: impl FnOnce(&str) -> std::io::Result<bool>,
+) -> Result<()> {
+    let manifest = read_manifest(addons_path, slug)?;
+    let directories = match &manifest {
+        Some(manifest) => manifest.directories.clone(),
+        None => fetch_latest(options, client, slug)?.directories,
+    };
+    let directories = without_skipped(&options.skip_dirs, &directories);
+
+    info!("Uninstalling {} from {}", slug, addons_path.display());
+    for target in &directories {
+        info!("  {}", target);
+    }
+
+    if options.dry_run {
+        for target in &directories {
+            let target_path = addons_path.join(target);
+            if target_path.is_dir() {
+                info!("[dry-run] Would remove {}", target_path.display());
+            } else {
+                info!("{} is already absent", target);
+            }
+        }
+        return Ok(());
+    }
+
+    check_client_not_running(options.force)?;
+    if !confirm("Remove these directories?")? {
+        info!("Uninstall cancelled");
+        return Ok(());
+    }
+
+    let removed = uninstall_directories(addons_path, &directories)?;
+    for target in &directories {
+        if removed.contains(target) {
+            info!("Removed {}", target);
+        } else {
+            info!("{} is already absent", target);
+        }
+    }
+    info!("Removed {} of {} directories", removed.len(), directories.len());
+
+    if let Some(manifest) = manifest {
+        let stores: Vec<String> = manifest.store.into_iter().chain(manifest.previous_store).collect();
+        for store in remove_stores(addons_path, &stores)? {
+            info!("Removed {}", store_path(addons_path, &store).display());
+        }
+        remove_manifest(addons_path, slug)?;
+    }
+
+    Ok(())
+}
+
+/// `err` followed by its sources, e.g. "could not read file `a.toc`: No
+/// such file or directory"
+fn describe(err: &dyn std::error::Error) -> String {
+    let mut description = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        description.push_str(": ");
+        description.push_str(&err.to_string());
+        source = err.source();
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_metadata;
+    use tempfile::Builder;
+
+    #[test]
+    fn report_serializes_to_json() {
+        let report = Report {
+            addon: "elvui".to_string(),
+            installed_version: None,
+            latest_version: "13.21".to_string(),
+            target_version: "13.21".to_string(),
+            action: Action::UpToDate,
+            directories_updated: Vec::new(),
+            directories_pruned: Vec::new(),
+            summary: "ElvUI already up to date (13.21) in 0.2s".to_string(),
+            elapsed_secs: 0.2,
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["action"], "up_to_date");
+        assert_eq!(json["installed_version"], serde_json::Value::Null);
+        assert_eq!(json["summary"], "ElvUI already up to date (13.21) in 0.2s");
+    }
+
+    #[test]
+    fn summary_describes_outcome() {
+        let mut report = Report {
+            addon: "elvui".to_string(),
+            installed_version: Some("13.20".to_string()),
+            latest_version: "13.21".to_string(),
+            target_version: "13.21".to_string(),
+            action: Action::Installed,
+            directories_updated: vec!["ElvUI".to_string(), "ElvUI_Options".to_string()],
+            directories_pruned: Vec::new(),
+            summary: String::new(),
+            elapsed_secs: 2.14,
+        };
+        assert_eq!(summary_line(&report, "ElvUI", "13.21", "dry run"), "Updated ElvUI 13.20 → 13.21 (2 directories) in 2.1s");
+
+        report.action = Action::UpToDate;
+        report.installed_version = Some("13.21".to_string());
+        assert_eq!(summary_line(&report, "ElvUI", "13.21", "dry run"), "ElvUI already up to date (13.21) in 2.1s");
+
+        report.directories_pruned = vec!["ElvUI_OptionsUI".to_string()];
+        assert_eq!(
+            summary_line(&report, "ElvUI", "13.21", "dry run"),
+            "ElvUI already up to date (13.21), pruned 1 directories in 2.1s"
+        );
+        report.directories_pruned.clear();
+
+        report.action = Action::None;
+        assert_eq!(summary_line(&report, "ElvUI", "13.22", "offline"), "ElvUI 13.22 not installed (offline) in 2.1s");
+    }
+
+    #[test]
+    fn status_shows_everything_known() {
+        let mut status = Status {
+            addon: "elvui".to_string(),
+            name: "ElvUI".to_string(),
+            installed_version: Some("13.20".to_string()),
+            latest_version: Some("13.21".to_string()),
+            update_available: Some(true),
+            metadata_cached_at: Some("2023-06-02T08:00:00Z".to_string()),
+            installed_at: Some("2023-06-01T12:00:00Z".to_string()),
+            pinned: None,
+            directories: vec!["ElvUI".to_string(), "ElvUI_Options".to_string()],
+            stale_directories: Vec::new(),
+        };
+        assert_eq!(
+            status.to_string(),
+            "ElvUI\n\
+             \x20 Installed:   13.20 (on 2023-06-01T12:00:00Z)\n\
+             \x20 Latest:      13.21 (cached 2023-06-02T08:00:00Z)\n\
+             \x20 Status:      update available\n\
+             \x20 Directories: ElvUI, ElvUI_Options"
+        );
+
+        status.installed_version = None;
+        status.installed_at = None;
+        status.latest_version = None;
+        status.update_available = None;
+        status.metadata_cached_at = None;
+        let human = status.to_string();
+        assert!(human.contains("Installed:   not installed\n"));
+        assert!(human.contains("Latest:      unknown\n"));
+        assert!(human.contains("Status:      unknown\n"));
+
+        status.stale_directories = vec!["ElvUI_Options".to_string()];
+        assert!(status.to_string().ends_with("\n  Stale:       ElvUI_Options (update to replace)"));
+    }
+
+    #[test]
+    fn status_schema_matches_json_output() {
+        let schema = serde_json::to_value(schemars::schema_for!(Status)).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+
+        let status = Status {
+            addon: "elvui".to_string(),
+            name: "ElvUI".to_string(),
+            installed_version: None,
+            latest_version: None,
+            update_available: None,
+            metadata_cached_at: None,
+            installed_at: None,
+            pinned: None,
+            directories: Vec::new(),
+            stale_directories: Vec::new(),
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        let mut fields: Vec<&String> = json.as_object().unwrap().keys().collect();
+        let mut documented: Vec<&String> = properties.keys().collect();
+        fields.sort();
+        documented.sort();
+        assert_eq!(fields, documented);
+        assert_eq!(schema["title"], "Status");
+    }
+
+    #[test]
+    fn comparison_shows_raw_versions_and_result() {
+        assert_eq!(comparison_result("13.20", "13.21"), "Lt");
+        assert_eq!(comparison_result("v13.21", "13.21"), "Eq");
+        assert_eq!(comparison_result("13.21", "13.21-beta"), "Gt");
+        assert_eq!(comparison_result("latest", "13.21"), "error: could not parse version `latest`");
+
+        let comparison = Comparison {
+            addon: "ElvUI".to_string(),
+            installed_version: Some("13.21 ".to_string()),
+            latest_version: "13.21".to_string(),
+            result: "Eq".to_string(),
+        };
+        assert_eq!(comparison.to_string(), r#"ElvUI: installed "13.21 " vs latest "13.21": Eq"#);
+    }
+
+    #[test]
+    fn installed_version_tells_fresh_install_from_unreadable() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let metadata = sample_metadata();
+        assert_eq!(installed_version(addons.path(), &metadata, Flavor::Retail), None);
+
+        std::fs::create_dir(addons.path().join("ElvUI")).unwrap();
+        std::fs::write(addons.path().join("ElvUI/ElvUI_Mainline.toc"), "## Title: ElvUI\n").unwrap();
+        assert_eq!(installed_version(addons.path(), &metadata, Flavor::Retail), None);
+
+        std::fs::write(addons.path().join("ElvUI/ElvUI_Mainline.toc"), "## Version: 13.21\n").unwrap();
+        assert_eq!(installed_version(addons.path(), &metadata, Flavor::Retail).as_deref(), Some("13.21"));
+    }
+
+    #[test]
+    fn skip_dirs_are_left_out() {
+        let skip_dirs = vec!["elvui_options".to_string(), "ElvUI_Libraries".to_string()];
+        let directories = vec!["ElvUI".to_string(), "ElvUI_Options".to_string(), "ElvUI_Libraries".to_string()];
+        assert_eq!(without_skipped(&skip_dirs, &directories), vec!["ElvUI"]);
+    }
+}
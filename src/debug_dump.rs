@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use crate::manifest::Manifest;
+use crate::metadata::ElvuiMetadata;
+
+/// What the dump collects over a run, once enabled
+static DUMP: Mutex<Option<DebugDump>> = Mutex::new(None);
+
+/// Everything needed to reproduce a run, written as JSON to attach to a bug
+/// report. Nothing that may be secret goes in: no proxy, hooks or other
+/// arguments, and the home directory is replaced with `~` in every path.
+#[derive(Serialize, Debug, Default)]
+pub struct DebugDump {
+    #[serde(skip)]
+    path: PathBuf,
+    pub version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub flavor: String,
+    pub source: String,
+    pub addons_paths: Vec<String>,
+    pub cache_dir: Option<String>,
+    pub config_path: Option<String>,
+    pub addons: BTreeMap<String, AddonDump>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct AddonDump {
+    pub metadata: Option<ElvuiMetadata>,
+    pub installed_version: Option<String>,
+    pub manifest: Option<Manifest>,
+    pub archive_entries: Vec<String>,
+}
+
+impl DebugDump {
+    pub fn new(path: &Path) -> Self {
+        DebugDump {
+            path: path.to_path_buf(),
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            ..Default::default()
+        }
+    }
+
+    /// Starts collecting into this dump for the rest of the run.
+    pub fn enable(self) {
+        *DUMP.lock().unwrap_or_else(PoisonError::into_inner) = Some(self);
+    }
+}
+
+/// Updates the dump with `f`, if one is being collected.
+pub fn record(f: impl FnOnce(&mut DebugDump)) {
+    if let Some(dump) = DUMP.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+        f(dump);
+    }
+}
+
+/// Updates the part of the dump about `slug` with `f`.
+pub fn record_addon(slug: &str, f: impl FnOnce(&mut AddonDump)) {
+    record(|dump| f(dump.addons.entry(slug.to_string()).or_default()));
+}
+
+/// Writes the dump, with the error the run ended with, if any. Returns the
+/// path written to, or `None` when no dump was being collected.
+pub fn finish(error: Option<&str>) -> Option<std::io::Result<PathBuf>> {
+    let mut dump = DUMP.lock().unwrap_or_else(PoisonError::into_inner).take()?;
+    dump.error = error.map(redact_home);
+
+    let result = serde_json::to_string_pretty(&dump)
+        .map_err(std::io::Error::from)
+        .and_then(|json| std::fs::write(&dump.path, json))
+        .map(|()| dump.path);
+    Some(result)
+}
+
+/// `path` as a string with the home directory replaced by `~`, since it
+/// usually contains the user's name.
+pub fn redact_path(path: &Path) -> String {
+    match home_dir().and_then(|home| path.strip_prefix(home).ok()) {
+        Some(relative) => Path::new("~").join(relative).display().to_string(),
+        None => path.display().to_string(),
+    }
+}
+
+fn redact_home(text: &str) -> String {
+    match home_dir() {
+        Some(home) => text.replace(&*home.to_string_lossy(), "~"),
+        None => text.to_string(),
+    }
+}
+
+/// The home directory, unless it is the filesystem root
+fn home_dir() -> Option<PathBuf> {
+    let home = std::env::var_os(if cfg!(target_os = "windows") { "USERPROFILE" } else { "HOME" })?;
+    Some(PathBuf::from(home)).filter(|home| home.parent().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn home_is_redacted() {
+        let home = match home_dir() {
+            Some(home) => home,
+            None => return,
+        };
+
+        assert_eq!(redact_path(&home.join("Games/AddOns")), "~/Games/AddOns");
+        assert_eq!(redact_path(Path::new("/opt/wow")), "/opt/wow");
+        assert_eq!(redact_home(&format!("could not read `{}/a.toc`", home.display())), "could not read `~/a.toc`");
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+
+/// Everything the library can fail with, so callers can decide per kind
+/// whether to retry, report or give up.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An HTTP request failed or the server answered with an error status
+    #[error(transparent)]
+    Network(#[from] reqwest::Error),
+
+    /// The download ended before the `Content-Length` the server promised
+    #[error("downloaded {received} of {expected} bytes")]
+    IncompleteDownload { received: u64, expected: u64 },
+
+    /// A metadata response wasn't the JSON we expected
+    #[error("could not parse metadata from {url}")]
+    MetadataParse {
+        url: String,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The metadata parsed but lacks something an install needs
+    #[error("invalid metadata from {url}: {reason}")]
+    InvalidMetadata { url: String, reason: String },
+
+    /// The flavor's `.toc` file couldn't be read from the addons directory
+    #[error("could not read file `{}`", path.display())]
+    TocNotFound {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The `.toc` file has no usable Version field
+    #[error("could not find Version field in `{}`", path.display())]
+    VersionParse { path: PathBuf },
+
+    /// A version string that can't be compared with another
+    #[error("could not parse version `{version}`")]
+    InvalidVersion { version: String },
+
+    /// The downloaded archive doesn't match the expected SHA-256
+    #[error("Checksum mismatch for downloaded archive! Expected {expected}, got {actual}")]
+    Checksum { expected: String, actual: String },
+
+    /// The archive is invalid or doesn't contain what the metadata lists
+    #[error("{message}")]
+    Extract {
+        message: String,
+        #[source]
+        source: Option<zip::result::ZipError>,
+    },
+
+    /// The manifest in the addons directory couldn't be parsed
+    #[error("could not parse manifest `{}`", path.display())]
+    Manifest {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// Changing the addons directory, or a command run on it, failed
+    #[error("{message}")]
+    Install {
+        message: String,
+        #[source]
+        source: Option<Box<Error>>,
+    },
+
+    /// With `InstallOptions::best_effort`, the listed directories couldn't
+    /// be swapped into place and were left as they were. The others were
+    /// updated and the manifest written.
+    #[error("could not update {}", describe_failed(failed))]
+    PartialInstall { failed: Vec<(String, Error)> },
+
+    /// Another run holds the lock on the addons directory
+    #[error(
+        "`{}` is in use by another elvui-manager{}",
+        path.display(),
+        pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default()
+    )]
+    Locked { path: PathBuf, pid: Option<u32> },
+
+    /// An interruption was requested with `interrupt::request`
+    #[error("interrupted")]
+    Interrupted,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn describe_failed(failed: &[(String, Error)]) -> String {
+    failed
+        .iter()
+        .map(|(target, err)| format!("`{}` ({})", target, err))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Error {
+    pub(crate) fn extract(message: impl Into<String>) -> Self {
+        Error::Extract { message: message.into(), source: None }
+    }
+
+    pub(crate) fn install(message: impl Into<String>) -> Self {
+        Error::Install { message: message.into(), source: None }
+    }
+
+    /// Wraps `err` in an `Install` error explaining what was being done.
+    pub(crate) fn install_context(message: impl Into<String>, err: impl Into<Error>) -> Self {
+        Error::Install { message: message.into(), source: Some(Box::new(err.into())) }
+    }
+
+    /// Whether the failure is transient, so the same request may succeed
+    /// if tried again. Client errors (4xx) are not retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Network(err) => is_retryable_reqwest(err),
+            Error::IncompleteDownload { .. } => true,
+            Error::Io(err) => {
+                // Reading a response body wraps reqwest errors in io::Error
+                if let Some(err) = err.get_ref().and_then(|inner| inner.downcast_ref::<reqwest::Error>()) {
+                    return is_retryable_reqwest(err);
+                }
+
+                matches!(
+                    err.kind(),
+                    std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::UnexpectedEof
+                        | std::io::ErrorKind::TimedOut
+                )
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_retryable_reqwest(err: &reqwest::Error) -> bool {
+    match err.status() {
+        Some(status) => status.is_server_error(),
+        None => err.is_connect() || err.is_timeout() || err.is_request() || err.is_body(),
+    }
+}
@@ -0,0 +1,141 @@
+use log::debug;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+use crate::metadata::ElvuiMetadata;
+
+/// A webhook URL, whose path is the token that logs must not show
+#[derive(Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct WebhookUrl(String);
+
+impl FromStr for WebhookUrl {
+    type Err = std::convert::Infallible;
+
+    fn from_str(url: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(WebhookUrl(url.to_string()))
+    }
+}
+
+impl fmt::Debug for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match reqwest::Url::parse(&self.0) {
+            Ok(url) if url.has_host() => write!(f, "{}://{}/***", url.scheme(), url.host_str().unwrap_or_default()),
+            _ => write!(f, "***"),
+        }
+    }
+}
+
+/// What `post_webhook` posts after an install. Discord shows `content` and
+/// Slack `text`, and both ignore the other fields.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct WebhookMessage {
+    pub content: String,
+    pub text: String,
+    pub addon: String,
+    pub previous_version: Option<String>,
+    pub version: String,
+    pub changelog_url: String,
+}
+
+/// Runs `command` through the platform shell with the addon being
+/// installed in its environment, failing unless it exits successfully.
+pub fn run_hook(command: &str, metadata: &ElvuiMetadata, addons_path: &Path) -> Result<()> {
+    let mut shell = if cfg!(target_os = "windows") {
+        let mut shell = std::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    debug!("Running hook: {}", command);
+    let status = shell
+        .arg(command)
+        .env("ELVUI_VERSION", &metadata.version)
+        .env("ELVUI_ADDON", &metadata.slug)
+        .env("ELVUI_ADDONS_PATH", addons_path)
+        .status()
+        .map_err(|err| Error::install_context(format!("could not run `{}`", command), err))?;
+
+    if !status.success() {
+        return Err(Error::install(format!("`{}` exited with {}", command, status)));
+    }
+
+    Ok(())
+}
+
+/// The message about `metadata` being installed over `previous_version`.
+pub fn webhook_message(metadata: &ElvuiMetadata, previous_version: Option<&str>, version: &str) -> WebhookMessage {
+    let mut content = match previous_version {
+        Some(previous) => format!("{} updated from {} to {}", metadata.name, previous, version),
+        None => format!("{} {} installed", metadata.name, version),
+    };
+    if !metadata.changelog_url.is_empty() {
+        content = format!("{}: {}", content, metadata.changelog_url);
+    }
+
+    WebhookMessage {
+        text: content.clone(),
+        content,
+        addon: metadata.slug.clone(),
+        previous_version: previous_version.map(str::to_string),
+        version: version.to_string(),
+        changelog_url: metadata.changelog_url.clone(),
+    }
+}
+
+pub fn post_webhook(client: &Client, webhook: &WebhookUrl, message: &WebhookMessage) -> Result<()> {
+    debug!("Posting to webhook {:?}", webhook);
+    // reqwest errors carry the full URL, token included
+    client
+        .post(&webhook.0)
+        .json(message)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(reqwest::Error::without_url)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_metadata;
+
+    #[test]
+    fn hook_failure_is_an_error() {
+        let metadata = sample_metadata();
+        assert!(run_hook("exit 0", &metadata, Path::new(".")).is_ok());
+
+        let err = run_hook("exit 3", &metadata, Path::new(".")).unwrap_err();
+        assert!(err.to_string().starts_with("`exit 3` exited with"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hook_sees_new_version() {
+        let metadata = sample_metadata();
+        assert!(run_hook(r#"test "$ELVUI_VERSION" = 13.21 && test "$ELVUI_ADDON" = elvui"#, &metadata, Path::new(".")).is_ok());
+    }
+
+    #[test]
+    fn webhook_message_describes_update() {
+        let metadata = ElvuiMetadata { changelog_url: String::new(), ..sample_metadata() };
+        let message = webhook_message(&metadata, Some("13.20"), "13.21");
+        assert_eq!(message.content, "ElvUI updated from 13.20 to 13.21");
+        assert_eq!(message.text, message.content);
+
+        let message = webhook_message(&sample_metadata(), None, "13.21");
+        assert_eq!(message.content, "ElvUI 13.21 installed: https://api.tukui.org/v1/changelog/elvui");
+        assert_eq!(message.previous_version, None);
+
+        let webhook = WebhookUrl("https://discord.com/api/webhooks/123/secret".to_string());
+        assert_eq!(format!("{:?}", webhook), "https://discord.com/***");
+    }
+}
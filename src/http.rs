@@ -0,0 +1,343 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use log::debug;
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::install::{Progress, ProgressEvent};
+use crate::interrupt;
+
+/// The user agent clients should identify with, `elvui-manager/<version>`
+pub const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+const RETRY_SLEEP_SLICE: Duration = Duration::from_millis(100);
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+const DOWNLOAD_FLUSH_INTERVAL: u64 = 1024 * 1024;
+
+const THROTTLE_SLICE: Duration = Duration::from_millis(100);
+
+/// Runs `f`, retrying with exponential backoff when it fails with a
+/// transient network error. Client errors (4xx) are returned immediately.
+/// The wait between attempts doubles up to `MAX_RETRY_DELAY`, and is cut
+/// short with `Error::Interrupted` once an interruption is requested.
+pub fn with_retries<T>(retries: u32, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 0;
+    loop {
+        interrupt::check()?;
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt < retries && err.is_retryable() => {
+                let delay = retry_delay(attempt);
+                attempt += 1;
+                debug!("Request failed ({:#}), retry {} of {} in {:?}", err, attempt, retries, delay);
+                sleep_interruptibly(delay)?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// How long to wait before retry `attempt + 1`
+fn retry_delay(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .and_then(|factor| RETRY_BASE_DELAY.checked_mul(factor))
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// Sleeps for `delay` in slices, failing with `Error::Interrupted` as soon
+/// as an interruption is requested.
+fn sleep_interruptibly(mut delay: Duration) -> Result<()> {
+    while !delay.is_zero() {
+        interrupt::check()?;
+        let slice = delay.min(RETRY_SLEEP_SLICE);
+        std::thread::sleep(slice);
+        delay -= slice;
+    }
+    interrupt::check()
+}
+
+/// Downloads `url` to `filename`, returning the response's `Content-Type`.
+///
+/// When `resumable` is set by an earlier attempt whose server advertised
+/// `Accept-Ranges: bytes`, a partial file is resumed with a range request.
+/// A server that answers with the full body, or with a range starting
+/// anywhere but the end of the file, starts the file over.
+/// `max_rate` caps the download at that many bytes per second. With
+/// `progress` set, the bytes received so far are reported to it instead of
+/// drawing a progress bar.
+pub(crate) fn download(
+    client: &Client,
+    url: &str,
+    filename: &Path,
+    quiet: bool,
+    max_rate: Option<u64>,
+    progress: Option<&Progress>,
+    resumable: &mut bool,
+) -> Result<Option<String>> {
+    let offset = match filename.metadata() {
+        Ok(metadata) if *resumable => metadata.len(),
+        _ => 0,
+    };
+
+    // Archives are compressed already, and a compressed response would hide
+    // the length for the progress bar and break resuming at a byte offset
+    let request = client.get(url).header(reqwest::header::ACCEPT_ENCODING, "identity");
+    let (mut response, resuming) = if offset > 0 {
+        debug!("Resuming download from byte {}", offset);
+        let ranged = request.try_clone().expect("a GET without a body can be cloned");
+        let response = ranged.header(reqwest::header::RANGE, format!("bytes={}-", offset)).send()?.error_for_status()?;
+        let range_start = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(content_range_start);
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT if range_start == Some(offset) => (response, true),
+            StatusCode::PARTIAL_CONTENT => {
+                // Appending any other range would corrupt the archive
+                debug!("Server sent a range starting at {:?} instead of {}, starting over", range_start, offset);
+                (request.send()?.error_for_status()?, false)
+            }
+            _ => {
+                debug!("Server ignored the range request, starting over");
+                (response, false)
+            }
+        }
+    } else {
+        (request.send()?.error_for_status()?, false)
+    };
+    if response.url().as_str() != url {
+        debug!("{} redirected to {}", url, response.url());
+    }
+
+    let (file, start) = if resuming {
+        (std::fs::OpenOptions::new().append(true).open(filename)?, offset)
+    } else {
+        *resumable = response
+            .headers()
+            .get(reqwest::header::ACCEPT_RANGES)
+            .is_some_and(|value| value.as_bytes() == b"bytes");
+        (File::create(filename)?, 0)
+    };
+    let expected = response.content_length().map(|length| length + start);
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    if let Some(progress) = progress {
+        progress.emit(ProgressEvent::DownloadStarted { url: url.to_string(), total: expected });
+    }
+    let bar = download_progress(expected, quiet || progress.is_some());
+    bar.set_position(start);
+
+    let mut received = start;
+    let mut throttle = max_rate.filter(|rate| *rate > 0).map(Throttle::new);
+    let mut file = BufWriter::with_capacity(DOWNLOAD_CHUNK_SIZE, file);
+    copy_chunked(&mut response, &mut file, |bytes| {
+        bar.inc(bytes);
+        received += bytes;
+        if let Some(progress) = progress {
+            progress.emit(ProgressEvent::DownloadProgress { bytes: received });
+        }
+        if let Some(throttle) = &mut throttle {
+            throttle.throttle(bytes);
+        }
+    })
+    .map_err(|err| if interrupt::is_requested() { Error::Interrupted } else { err.into() })?;
+    bar.finish_and_clear();
+
+    verify_download_size(filename, expected)?;
+    Ok(content_type)
+}
+
+/// The first byte of a `Content-Range: bytes <start>-<end>/<length>` header
+fn content_range_start(value: &str) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (start, _) = range.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+/// Fails with a retryable error when the file is shorter than the
+/// `Content-Length` promised, so the next attempt can resume it.
+fn verify_download_size(filename: &Path, expected: Option<u64>) -> Result<()> {
+    let actual = filename.metadata()?.len();
+    match expected {
+        Some(expected) if actual != expected => Err(Error::IncompleteDownload { received: actual, expected }),
+        _ => Ok(()),
+    }
+}
+
+/// Copies `reader` into `writer` one fixed-size chunk at a time, so memory
+/// stays bounded however large the download is. `on_chunk` is called with
+/// the size of every chunk written. Returns the total bytes copied, or
+/// stops with an error once an interruption is requested.
+fn copy_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W, mut on_chunk: impl FnMut(u64)) -> std::io::Result<u64> {
+    let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
+    let mut total: u64 = 0;
+    let mut unflushed: u64 = 0;
+
+    loop {
+        if interrupt::is_requested() {
+            return Err(std::io::Error::other("interrupted"));
+        }
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        writer.write_all(&buffer[..read])?;
+        total += read as u64;
+        unflushed += read as u64;
+        on_chunk(read as u64);
+
+        if unflushed >= DOWNLOAD_FLUSH_INTERVAL {
+            writer.flush()?;
+            unflushed = 0;
+        }
+    }
+
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Caps throughput by sleeping whenever more bytes have been copied than
+/// `rate` bytes per second allows for the time since it started.
+struct Throttle {
+    rate: u64,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        Throttle { rate, started: Instant::now(), bytes: 0 }
+    }
+
+    fn throttle(&mut self, bytes: u64) {
+        // Slept in slices, so an interruption isn't held up by a slow rate
+        let mut delay = self.delay(bytes, self.started.elapsed());
+        while !delay.is_zero() && !interrupt::is_requested() {
+            let slice = delay.min(THROTTLE_SLICE);
+            std::thread::sleep(slice);
+            delay -= slice;
+        }
+    }
+
+    /// How long to wait after `bytes` more, `elapsed` after starting.
+    fn delay(&mut self, bytes: u64, elapsed: Duration) -> Duration {
+        self.bytes += bytes;
+        Duration::from_secs_f64(self.bytes as f64 / self.rate as f64).saturating_sub(elapsed)
+    }
+}
+
+/// A progress bar sized from `Content-Length`, or a spinner when the length
+/// is unknown. Nothing is drawn when quiet or when stderr isn't a terminal.
+pub fn download_progress(length: Option<u64>, quiet: bool) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+
+    match length {
+        Some(length) => ProgressBar::new(length).with_style(
+            ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({eta})").unwrap(),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("{spinner} {bytes} downloaded").unwrap(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn retries_transient_errors() {
+        let mut calls = 0;
+        let result: Result<()> = with_retries(1, || {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset).into())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn retry_delay_is_capped() {
+        assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), RETRY_BASE_DELAY * 4);
+        assert_eq!(retry_delay(10), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(40), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn does_not_retry_fatal_errors() {
+        let mut calls = 0;
+        let result: Result<()> = with_retries(3, || {
+            calls += 1;
+            Err(Error::install("not retryable"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn parses_content_range_start() {
+        assert_eq!(content_range_start("bytes 100-199/200"), Some(100));
+        assert_eq!(content_range_start("bytes 0-199/*"), Some(0));
+        assert_eq!(content_range_start("bytes */200"), None);
+        assert_eq!(content_range_start("items 100-199/200"), None);
+    }
+
+    #[test]
+    fn copies_in_bounded_chunks() {
+        let data = vec![7u8; DOWNLOAD_CHUNK_SIZE * 2 + 10];
+        let mut output = Vec::new();
+        let mut chunks = Vec::new();
+
+        let total = copy_chunked(&mut data.as_slice(), &mut output, |bytes| chunks.push(bytes)).unwrap();
+
+        assert_eq!(total, data.len() as u64);
+        assert_eq!(output, data);
+        assert!(chunks.iter().all(|bytes| *bytes <= DOWNLOAD_CHUNK_SIZE as u64));
+    }
+
+    #[test]
+    fn throttle_waits_when_ahead_of_rate() {
+        let mut throttle = Throttle::new(1000);
+
+        assert_eq!(throttle.delay(500, Duration::from_millis(100)), Duration::from_millis(400));
+        assert_eq!(throttle.delay(500, Duration::from_millis(1000)), Duration::ZERO);
+        assert_eq!(throttle.delay(1000, Duration::from_millis(1500)), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn incomplete_download_is_retryable() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let filename = dir.path().join("elvui.zip");
+        std::fs::write(&filename, "abc").unwrap();
+
+        assert!(verify_download_size(&filename, Some(3)).is_ok());
+        assert!(verify_download_size(&filename, None).is_ok());
+
+        let err = verify_download_size(&filename, Some(10)).unwrap_err();
+        assert!(err.is_retryable());
+    }
+}
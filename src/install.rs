@@ -0,0 +1,1778 @@
+use log::debug;
+use reqwest::blocking::Client;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tempfile::{Builder, TempDir};
+
+use crate::archive::{check_zip_magic, extract_directories, open_archive, required_space, verify_checksum};
+use crate::error::{Error, Result};
+use crate::http::{download, with_retries};
+use crate::interrupt::CriticalSection;
+use crate::link::{is_link, link_dir, remove_link};
+use crate::manifest::{read_manifest, write_manifest, Manifest};
+use crate::metadata::{ElvuiMetadata, Flavor};
+use crate::toc::fetch_addon_version;
+use crate::verify::{compare_directories, directory_hashes, DirectoryDiff, FileHashes};
+
+#[derive(Debug, Default)]
+pub struct InstallOptions {
+    /// Keep the replaced directories as `<name>.bak` for `rollback_directories`
+    pub backup: bool,
+    /// Hide the download progress bar
+    pub quiet: bool,
+    /// Cap the download at this many bytes per second
+    pub max_rate: Option<u64>,
+    /// Leave the temporary directory with the download and extracted
+    /// files behind, whether or not the install succeeds
+    pub keep_temp: bool,
+    /// Install from this local zip instead of downloading `metadata.url`.
+    /// The metadata still says which directories to move into place
+    pub archive: Option<PathBuf>,
+    /// Keep the downloaded archive in this directory until `commit`
+    /// succeeds, as `<slug>-<version>.zip`. A run that failed after the
+    /// download leaves it there, and the next run reuses it when it is
+    /// still a valid archive instead of downloading it again.
+    pub download_cache: Option<PathBuf>,
+    /// Where to create the temporary directory instead of the system's,
+    /// e.g. on the same volume as the addons so the swap is a rename
+    pub temp_dir: Option<PathBuf>,
+    /// Expected SHA-256 of the downloaded archive
+    pub sha256: Option<String>,
+    pub retries: u32,
+    /// Recorded in the manifest so later runs stay on this version
+    pub pin: Option<String>,
+    /// Download URLs to try in order when `metadata.url` fails, in which
+    /// `{slug}` and `{version}` are replaced with the addon's
+    pub mirrors: Vec<String>,
+    /// Directories from an earlier release to keep recording in the
+    /// manifest, so they can still be found by `orphaned_directories` later
+    pub retain: Vec<String>,
+    /// Record the SHA-256 of every installed file in the manifest, so
+    /// `verify::compare_with_hashes` can check them without a download
+    pub checksums: bool,
+    /// Swap every directory on its own, so one that fails to move is left
+    /// as it was while the others are still updated, instead of putting
+    /// all of them back. `commit` then fails with `Error::PartialInstall`.
+    pub best_effort: bool,
+    /// Keep the files of the installed directories that the release doesn't
+    /// ship, such as a hand-edited config `.lua`, instead of replacing each
+    /// directory wholesale. The files the release ships are still replaced.
+    pub overlay: bool,
+    /// Move the release into its own directory in `STORE_DIR` and link
+    /// every addon directory to it instead of moving them into place, so
+    /// `rollback_links` can switch back by re-pointing the links.
+    /// `best_effort` has no effect.
+    pub link: bool,
+    /// Called with every step of the install as it happens. When set, no
+    /// progress bar is drawn and `quiet` has no effect.
+    pub progress: Option<Progress>,
+}
+
+/// A step of `install` reported to `InstallOptions::progress`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// A download from `url` began, `total` bytes long when the server said
+    DownloadStarted { url: String, total: Option<u64> },
+    /// `bytes` of the archive have been received so far
+    DownloadProgress { bytes: u64 },
+    Extracting,
+    /// The directory `name` is being swapped into place
+    SwappingDir { name: String },
+    Done,
+}
+
+/// The callback `ProgressEvent`s are sent to
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(ProgressEvent) + Send + Sync>);
+
+impl Progress {
+    pub fn new(callback: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        Progress(Arc::new(callback))
+    }
+
+    pub fn emit(&self, event: ProgressEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// A step `install` would take, as reported by `plan_install`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Operation {
+    Download { url: String },
+    OpenArchive { path: PathBuf },
+    VerifyChecksum { sha256: String },
+    Extract,
+    Backup { from: PathBuf, to: PathBuf },
+    Remove { path: PathBuf },
+    Move { name: String, to: PathBuf },
+    Overlay { name: String, to: PathBuf },
+    Link { name: String, to: PathBuf, store: PathBuf },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Download { url } => write!(f, "download {} to a temporary elvui.zip", url),
+            Operation::OpenArchive { path } => write!(f, "use the local archive {} as elvui.zip", path.display()),
+            Operation::VerifyChecksum { sha256 } => write!(f, "verify elvui.zip has SHA-256 {}", sha256),
+            Operation::Extract => write!(f, "extract elvui.zip into a temporary directory"),
+            Operation::Backup { from, to } => write!(f, "back up {} to {}", from.display(), to.display()),
+            Operation::Remove { path } => write!(f, "remove {}", path.display()),
+            Operation::Move { name, to } => write!(f, "move {} to {}", name, to.display()),
+            Operation::Overlay { name, to } => {
+                write!(f, "copy {} over {}, keeping the files it doesn't ship", name, to.display())
+            }
+            Operation::Link { name, to, store } => {
+                write!(f, "move {} into {} and link {} to it", name, store.display(), to.display())
+            }
+        }
+    }
+}
+
+pub fn install(client: &Client, addons_path: &Path, metadata: ElvuiMetadata, options: &InstallOptions) -> Result<()> {
+    prepare_install(client, addons_path, metadata, options)?.commit()
+}
+
+/// An archive that has been downloaded and fully extracted into a temporary
+/// directory, ready to be swapped into the addons directory by `commit`.
+#[derive(Debug)]
+pub struct PreparedInstall {
+    tempdir: TempDir,
+    extracted_path: PathBuf,
+    addons_path: PathBuf,
+    metadata: ElvuiMetadata,
+    downloaded_from: String,
+    /// The archive in `InstallOptions::download_cache`, to remove once
+    /// it is installed
+    cached_archive: Option<PathBuf>,
+    archive_entries: Vec<String>,
+    backup: bool,
+    keep_temp: bool,
+    pin: Option<String>,
+    retain: Vec<String>,
+    checksums: bool,
+    best_effort: bool,
+    overlay: bool,
+    link: bool,
+    progress: Option<Progress>,
+}
+
+/// Does everything `install` does short of touching the addons directory:
+/// downloads the archive, verifies it and extracts it. Several addons can be
+/// prepared concurrently, as long as their `commit`s run one at a time.
+pub fn prepare_install(client: &Client, addons_path: &Path, metadata: ElvuiMetadata, options: &InstallOptions) -> Result<PreparedInstall> {
+    if !addons_path.is_dir() {
+        return Err(Error::install("Unable to install! Addons path does not exist!"));
+    }
+    check_install_directories(&metadata)?;
+
+    // create temp dir
+    let mut builder = Builder::new();
+    builder.prefix("elvui-manager");
+    let tempdir = match &options.temp_dir {
+        Some(temp_dir) => builder.tempdir_in(temp_dir).map_err(|err| {
+            Error::install_context(format!("could not create a temporary directory in `{}`", temp_dir.display()), err)
+        })?,
+        None => builder.tempdir()?,
+    };
+    debug!("tempdir: {:#?}", tempdir);
+
+    let cached_archive = match (&options.archive, &options.download_cache) {
+        (None, Some(dir)) => Some(cached_archive_path(dir, &metadata)),
+        _ => None,
+    };
+    let (downloaded_from, archive_entries) = match download_and_extract(client, tempdir.path(), addons_path, &metadata, cached_archive.as_deref(), options) {
+        Ok(parts) => parts,
+        Err(err) if options.keep_temp => return Err(kept_temp_error(tempdir, err)),
+        Err(err) => return Err(err),
+    };
+
+    Ok(PreparedInstall {
+        extracted_path: tempdir.path().join("elvui"),
+        tempdir,
+        addons_path: addons_path.to_path_buf(),
+        metadata,
+        downloaded_from,
+        cached_archive,
+        archive_entries,
+        backup: options.backup,
+        keep_temp: options.keep_temp,
+        pin: options.pin.clone(),
+        retain: options.retain.clone(),
+        checksums: options.checksums,
+        best_effort: options.best_effort,
+        overlay: options.overlay,
+        link: options.link,
+        progress: options.progress.clone(),
+    })
+}
+
+/// Downloads, verifies and extracts the archive into `temp_path/elvui`,
+/// returning the URL it came from and its entries. With `cached` set, the
+/// archive is downloaded there instead, or reused when it already is.
+fn download_and_extract(
+    client: &Client,
+    temp_path: &Path,
+    addons_path: &Path,
+    metadata: &ElvuiMetadata,
+    cached: Option<&Path>,
+    options: &InstallOptions,
+) -> Result<(String, Vec<String>)> {
+    let (filename, downloaded_from) = match (&options.archive, cached) {
+        (Some(archive), _) => {
+            if !archive.is_file() {
+                return Err(Error::install(format!("Archive `{}` does not exist!", archive.display())));
+            }
+            check_zip_magic(archive, None)?;
+            (archive.clone(), archive.display().to_string())
+        }
+        (None, Some(cached)) if is_reusable(cached, options) => {
+            debug!("Reusing {:?} downloaded earlier", cached);
+            (cached.to_path_buf(), cached.display().to_string())
+        }
+        (None, Some(cached)) if cached.parent().is_some_and(|dir| std::fs::create_dir_all(dir).is_ok()) => {
+            // Renamed into place only once complete, so a partial download
+            // is never mistaken for the archive
+            let partial = cached.with_extension("zip.part");
+            let downloaded_from = download_archive(client, metadata, &partial, options)?;
+            std::fs::rename(&partial, cached)?;
+            debug!("downloaded to {:?}", cached);
+            (cached.to_path_buf(), downloaded_from)
+        }
+        (None, _) => {
+            // download archive
+            let filename = temp_path.join("elvui.zip");
+            debug!("filename: {:#?}", &filename);
+            let downloaded_from = download_archive(client, metadata, &filename, options)?;
+            debug!("copied response");
+            (filename, downloaded_from)
+        }
+    };
+
+    if let Some(expected) = &options.sha256 {
+        verify_checksum(&filename, expected)?;
+        debug!("verified checksum");
+    }
+
+    // Fully extract and validate the archive before touching any existing
+    // directories, so a bad download can't leave the install half deleted.
+    if let Some(progress) = &options.progress {
+        progress.emit(ProgressEvent::Extracting);
+    }
+    let extracted_path = temp_path.join("elvui");
+    let mut archive = open_archive(&filename)?;
+    let archive_entries = archive.file_names().map(str::to_string).collect();
+
+    let required = required_space(&mut archive, &metadata.directories)?;
+    check_disk_space(temp_path, required)?;
+    check_disk_space(addons_path, required)?;
+
+    extract_directories(&mut archive, &extracted_path, &metadata.directories)?;
+    debug!("extracted archive");
+
+    Ok((downloaded_from, archive_entries))
+}
+
+/// Keeps `tempdir` for inspection, naming it in the error `err` is wrapped in.
+fn kept_temp_error(tempdir: TempDir, err: Error) -> Error {
+    let path = tempdir.into_path();
+    Error::install_context(format!("Install failed, temporary files kept in `{}`", path.display()), err)
+}
+
+/// Downloads the archive to `filename` from `metadata.url`, or from each of
+/// the mirrors in turn when that fails, returning the URL it came from.
+/// A response that isn't a zip archive counts as a failure too.
+fn download_archive(client: &Client, metadata: &ElvuiMetadata, filename: &Path, options: &InstallOptions) -> Result<String> {
+    let mirrors = options.mirrors.iter().map(|mirror| mirror_url(mirror, metadata));
+    let mut last_err = None;
+
+    for url in std::iter::once(metadata.url.clone()).chain(mirrors) {
+        debug!("Downloading {}", url);
+        let mut resumable = false;
+        let result = with_retries(options.retries, || {
+            download(client, &url, filename, options.quiet, options.max_rate, options.progress.as_ref(), &mut resumable)
+        })
+            .and_then(|content_type| check_zip_magic(filename, content_type.as_deref()));
+
+        match result {
+            Ok(()) => return Ok(url),
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            Err(err) => {
+                debug!("Download from {} failed: {}", url, err);
+                last_err = Some(err);
+            }
+        }
+    }
+
+    let err = last_err.expect("the primary url is always tried");
+    if options.mirrors.is_empty() {
+        return Err(err);
+    }
+    Err(Error::install_context(
+        format!("Download failed from {} and {} mirrors", metadata.url, options.mirrors.len()),
+        err,
+    ))
+}
+
+/// Where `InstallOptions::download_cache` keeps the archive of `metadata`,
+/// named like the release's store
+pub fn cached_archive_path(dir: &Path, metadata: &ElvuiMetadata) -> PathBuf {
+    dir.join(format!("{}.zip", store_name(&metadata.slug, &metadata.version)))
+}
+
+/// Whether the archive at `path` was fully downloaded and, with
+/// `options.sha256`, has the expected checksum. One that can't be used is
+/// removed, so it is downloaded again.
+fn is_reusable(path: &Path, options: &InstallOptions) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    let valid = check_zip_magic(path, None)
+        .and_then(|()| open_archive(path).map(drop))
+        .and_then(|()| options.sha256.as_ref().map_or(Ok(()), |expected| verify_checksum(path, expected)));
+    if let Err(err) = valid {
+        debug!("Not reusing {:?}: {}", path, err);
+        let _ = std::fs::remove_file(path);
+        return false;
+    }
+    true
+}
+
+fn mirror_url(mirror: &str, metadata: &ElvuiMetadata) -> String {
+    mirror.replace("{slug}", &metadata.slug).replace("{version}", &metadata.version)
+}
+
+impl PreparedInstall {
+    /// The URL the archive was downloaded from, a mirror's when the primary failed.
+    pub fn downloaded_from(&self) -> &str {
+        &self.downloaded_from
+    }
+
+    /// Whether the archive was downloaded by an earlier run and reused from
+    /// `InstallOptions::download_cache`, in which case `downloaded_from` is
+    /// its path.
+    pub fn reused_archive(&self) -> bool {
+        self.cached_archive.as_ref().is_some_and(|cached| cached.display().to_string() == self.downloaded_from)
+    }
+
+    /// The temporary directory holding the download and extracted files.
+    pub fn temp_path(&self) -> &Path {
+        self.tempdir.path()
+    }
+
+    /// Takes the version to record from the extracted release's `.toc`
+    /// instead of the metadata, for a local archive whose version isn't
+    /// known up front.
+    pub fn use_release_version(&mut self, flavor: Flavor) -> Result<&str> {
+        self.metadata.version = fetch_addon_version(&self.extracted_path, &self.metadata, flavor)?;
+        Ok(&self.metadata.version)
+    }
+
+    /// Compares the installed directories with the extracted release,
+    /// without changing anything.
+    pub fn verify(&self) -> Result<Vec<DirectoryDiff>> {
+        compare_directories(&self.extracted_path, &self.addons_path, &self.metadata.directories)
+    }
+
+    /// Limits `commit` to replacing `directories`. The others stay as they
+    /// are and stay recorded in the manifest.
+    pub fn restrict_to(&mut self, directories: &[String]) {
+        let (swapped, kept) = self.metadata.directories.drain(..).partition(|target| directories.contains(target));
+        self.metadata.directories = swapped;
+        self.retain.extend(kept);
+    }
+
+    /// The name of every entry in the downloaded archive.
+    pub fn archive_entries(&self) -> &[String] {
+        &self.archive_entries
+    }
+
+    /// `.lua` files in the directories about to be replaced that the new
+    /// release doesn't ship, such as settings saved inside an addon
+    /// directory. They are lost by `commit` unless backups are kept, or
+    /// none are listed when `InstallOptions::overlay` keeps them.
+    pub fn unshipped_lua_files(&self) -> Vec<PathBuf> {
+        let mut unshipped = Vec::new();
+        if self.overlay {
+            return unshipped;
+        }
+
+        for target in &self.metadata.directories {
+            let target_path = self.addons_path.join(target);
+            for file in lua_files(&target_path) {
+                let relative = file.strip_prefix(&target_path).unwrap_or(&file);
+                if !self.extracted_path.join(target).join(relative).is_file() {
+                    unshipped.push(file);
+                }
+            }
+        }
+
+        unshipped
+    }
+
+    /// The hashes to record in the manifest: those of the `release`
+    /// directories just swapped into place, and the ones recorded earlier
+    /// for the retained directories, which weren't touched.
+    fn checksums(&self, release: BTreeMap<String, FileHashes>, failed: &[(String, Error)]) -> Result<BTreeMap<String, FileHashes>> {
+        let previous = read_manifest(&self.addons_path, &self.metadata.slug).ok().flatten();
+        let mut checksums = previous.and_then(|manifest| manifest.checksums).unwrap_or_default();
+        checksums.retain(|target, _| self.retain.contains(target));
+
+        let swapped: BTreeMap<String, FileHashes> = release
+            .into_iter()
+            .filter(|(target, _)| !failed.iter().any(|(failed, _)| failed == target))
+            .collect();
+        debug!("hashed the files of {} directories", swapped.len());
+        checksums.extend(swapped);
+        Ok(checksums)
+    }
+
+    /// The directories left on an earlier release: the `failed` ones, and
+    /// those recorded as stale before that this install left alone.
+    fn stale(&self, failed: &[(String, Error)]) -> Vec<String> {
+        let previous = read_manifest(&self.addons_path, &self.metadata.slug).ok().flatten();
+        let mut stale: Vec<String> = previous
+            .map(|manifest| manifest.stale)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|target| self.retain.contains(target) && !self.metadata.directories.contains(target))
+            .collect();
+        for (target, _) in failed {
+            if !stale.contains(target) {
+                stale.push(target.clone());
+            }
+        }
+        stale
+    }
+
+    /// Links the extracted directories into place with `link_directories`,
+    /// then removes the stores no longer needed: the one linked before is
+    /// kept for `rollback_links` when backups are. Returns the new store
+    /// and the one kept, to record in the manifest.
+    fn link_directories(&self) -> Result<(String, Option<String>)> {
+        let previous = read_manifest(&self.addons_path, &self.metadata.slug).ok().flatten();
+        let (current, earlier) = previous.map(|manifest| (manifest.store, manifest.previous_store)).unwrap_or_default();
+
+        let store = store_name(&self.metadata.slug, &self.metadata.version);
+        link_directories(
+            &self.addons_path,
+            &self.extracted_path,
+            &store,
+            &self.metadata.directories,
+            self.backup,
+            self.progress.as_ref(),
+        )?;
+
+        // Reinstalling the linked release keeps what it could roll back to
+        if current.as_deref() == Some(store.as_str()) {
+            return Ok((store, earlier));
+        }
+        let kept = current.clone().filter(|_| self.backup);
+        let unused: Vec<String> = [earlier, current.filter(|_| !self.backup)]
+            .into_iter()
+            .flatten()
+            .filter(|name| *name != store)
+            .collect();
+        remove_stores(&self.addons_path, &unused)?;
+        Ok((store, kept))
+    }
+
+    /// Swaps the extracted directories into place and writes the manifest.
+    pub fn commit(self) -> Result<()> {
+        // Hashed before an overlay adds the installed files the release
+        // doesn't ship, which aren't the release's to verify
+        let release = if self.checksums {
+            Some(directory_hashes(&self.extracted_path, &self.metadata.directories)?)
+        } else {
+            None
+        };
+        if self.overlay {
+            overlay_installed(&self.addons_path, &self.extracted_path, &self.metadata.directories)?;
+        }
+
+        let mut failed = Vec::new();
+        let mut stores = (None, None);
+        let swapped = if self.link {
+            self.link_directories().map(|(store, previous_store)| stores = (Some(store), previous_store))
+        } else if self.best_effort {
+            failed = swap_directories_best_effort(
+                &self.addons_path,
+                &self.extracted_path,
+                &self.metadata.directories,
+                self.backup,
+                self.progress.as_ref(),
+            );
+            Ok(())
+        } else {
+            swap_directories(
+                &self.addons_path,
+                &self.extracted_path,
+                &self.metadata.directories,
+                self.backup,
+                self.progress.as_ref(),
+            )
+        };
+        if let Err(err) = swapped {
+            if self.keep_temp {
+                return Err(kept_temp_error(self.tempdir, err));
+            }
+            return Err(err);
+        }
+
+        let checksums = match release {
+            Some(release) => Some(self.checksums(release, &failed)?),
+            None => None,
+        };
+
+        let stale = self.stale(&failed);
+        let mut directories = self.metadata.directories;
+        for target in self.retain {
+            if !directories.contains(&target) {
+                directories.push(target);
+            }
+        }
+
+        let manifest = Manifest {
+            version: self.metadata.version,
+            installed_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            directories,
+            pinned: self.pin,
+            prerelease: self.metadata.prerelease,
+            checksums,
+            store: stores.0,
+            previous_store: stores.1,
+            stale,
+        };
+        write_manifest(&self.addons_path, &self.metadata.slug, &manifest)?;
+        debug!("wrote manifest");
+
+        if let Some(cached) = &self.cached_archive {
+            if let Err(err) = std::fs::remove_file(cached) {
+                debug!("Unable to remove {:?}: {}", cached, err);
+            }
+        }
+
+        if self.keep_temp {
+            debug!("Keeping {:?}", self.tempdir.into_path());
+        } else {
+            self.tempdir.close()?;
+        }
+        if !failed.is_empty() {
+            return Err(Error::PartialInstall { failed });
+        }
+        if let Some(progress) = &self.progress {
+            progress.emit(ProgressEvent::Done);
+        }
+        Ok(())
+    }
+}
+
+/// Lists the operations `install` would perform, without touching disk.
+pub fn plan_install(addons_path: &Path, metadata: &ElvuiMetadata, options: &InstallOptions) -> Result<Vec<Operation>> {
+    if !addons_path.is_dir() {
+        return Err(Error::install("Unable to install! Addons path does not exist!"));
+    }
+    check_install_directories(metadata)?;
+
+    let mut plan = match &options.archive {
+        Some(path) => vec![Operation::OpenArchive { path: path.clone() }],
+        None => vec![Operation::Download { url: metadata.url.clone() }],
+    };
+    if let Some(expected) = &options.sha256 {
+        plan.push(Operation::VerifyChecksum { sha256: expected.clone() });
+    }
+    plan.push(Operation::Extract);
+
+    for target in &metadata.directories {
+        let target_path = addons_path.join(target);
+
+        if options.link && is_link(&target_path) {
+            // Only the link is replaced
+        } else if target_path.is_dir() {
+            if options.backup {
+                plan.push(Operation::Backup { from: target_path.clone(), to: backup_path(addons_path, target) });
+            } else if !options.overlay {
+                plan.push(Operation::Remove { path: target_path.clone() });
+            }
+        }
+        if options.link {
+            let store = store_path(addons_path, &store_name(&metadata.slug, &metadata.version));
+            plan.push(Operation::Link { name: target.clone(), to: target_path, store });
+        } else if options.overlay && target_path.is_dir() {
+            plan.push(Operation::Overlay { name: target.clone(), to: target_path });
+        } else {
+            plan.push(Operation::Move { name: target.clone(), to: target_path });
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Refuses directory names that aren't a single plain addon directory, so
+/// bad metadata can never make an install, rollback or uninstall reach
+/// outside the addons directory, or anywhere near `WTF/` where the game
+/// keeps SavedVariables.
+fn check_directory_names(directories: &[String]) -> Result<()> {
+    for target in directories {
+        let mut components = Path::new(target).components();
+        let plain = matches!((components.next(), components.next()), (Some(Component::Normal(_)), None));
+
+        if !plain || target.eq_ignore_ascii_case("WTF") {
+            return Err(Error::install(format!("Refusing to touch `{}`, it is not an addon directory!", target)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the directories `metadata` would install: there must be at least
+/// one, or the install would download everything and replace nothing.
+fn check_install_directories(metadata: &ElvuiMetadata) -> Result<()> {
+    if metadata.directories.is_empty() {
+        return Err(Error::install(format!(
+            "The metadata for {} lists no addon directories to install, the API may have changed!",
+            metadata.name
+        )));
+    }
+    check_directory_names(&metadata.directories)
+}
+
+/// Every `.lua` file under `dir`, or none if it can't be read.
+fn lua_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(lua_files(&path));
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("lua")) {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+pub(crate) fn check_disk_space(path: &Path, required: u64) -> Result<()> {
+    let available = fs2::available_space(path).map_err(|err| {
+        Error::install_context(format!("could not check free space for `{}`", path.display()), err)
+    })?;
+    debug!("{:?} has {} bytes free, need {}", path, available, required);
+
+    if available < required {
+        return Err(Error::install(format!(
+            "Not enough disk space in `{}`! Need {} bytes but only {} are available.",
+            path.display(), required, available
+        )));
+    }
+
+    Ok(())
+}
+
+/// Renames `from` to `to`, falling back to a recursive copy and delete when
+/// they are on different filesystems (e.g. a tmpfs `/tmp` on Linux).
+fn move_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            debug!("Cross-device rename of {:?}, copying instead", from);
+            if let Err(err) = copy_dir_all(from, to) {
+                let _ = remove_dir_all(to);
+                return Err(err);
+            }
+            remove_dir_all(from)
+        }
+        result => result,
+    }
+}
+
+/// `std::fs::remove_dir_all`, retried after clearing read-only attributes
+/// when it is denied. Windows refuses to delete read-only files, which some
+/// extraction tools leave behind.
+fn remove_dir_all(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+            debug!("Clearing read-only attributes under {:?}", path);
+            clear_readonly(path)?;
+            std::fs::remove_dir_all(path)
+        }
+        result => result,
+    }
+}
+
+// The directory is about to be deleted, so it doesn't matter that this
+// makes everything in it writable by everyone on Unix
+#[allow(clippy::permissions_set_readonly_false)]
+fn clear_readonly(path: &Path) -> std::io::Result<()> {
+    let mut permissions = std::fs::symlink_metadata(path)?.permissions();
+    if permissions.readonly() {
+        permissions.set_readonly(false);
+        std::fs::set_permissions(path, permissions)?;
+    }
+
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            clear_readonly(&entry?.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_dir_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(to)?;
+
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies the files of the installed `directories` that the release
+/// extracted to `extracted_path` doesn't ship into it, so swapping the
+/// release into place keeps them. Where both have a file, the release's
+/// wins. Nothing in `addons_path` is changed.
+fn overlay_installed(addons_path: &Path, extracted_path: &Path, directories: &[String]) -> Result<()> {
+    for target in directories {
+        let installed = addons_path.join(target);
+        let extracted = extracted_path.join(target);
+        if installed.is_dir() && extracted.is_dir() {
+            copy_missing(&installed, &extracted)
+                .map_err(|err| Error::install_context(format!("could not keep the installed files of `{}`", target), err))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies everything under `from` that isn't in `to` yet.
+fn copy_missing(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            if !dest.exists() {
+                copy_dir_all(&entry.path(), &dest)?;
+            } else if dest.is_dir() {
+                copy_missing(&entry.path(), &dest)?;
+            }
+        } else if !dest.exists() {
+            debug!("Keeping {:?}, the release doesn't ship it", entry.path());
+            std::fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the previous copy of `target` is kept after an install
+pub fn backup_path(addons_path: &Path, target: &str) -> PathBuf {
+    addons_path.join(format!("{}.bak", target))
+}
+
+/// Swaps the extracted directories into the addons dir as a unit.
+///
+/// Every existing directory is moved aside before any new one is moved in,
+/// and if any step fails everything is put back the way it was. With
+/// `backup` set, the old directories are kept as `<name>.bak` for `rollback`
+/// until the next install replaces them; otherwise they are deleted once
+/// the swap succeeds.
+fn swap_directories(
+    addons_path: &Path,
+    extracted_path: &Path,
+    directories: &[String],
+    backup: bool,
+    progress: Option<&Progress>,
+) -> Result<()> {
+    let _section = CriticalSection::enter();
+    // Nothing is moved aside unless every new directory is there to replace it
+    check_extracted(extracted_path, directories)?;
+
+    let mut installed: Vec<PathBuf> = Vec::new();
+    let mut moved_aside: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    let result = (|| -> Result<()> {
+        // Move all the old directories out of the way first
+        for target in directories {
+            let target_path = addons_path.join(target);
+            let aside_path = aside_path(addons_path, target, backup);
+
+            if aside_path.is_dir() {
+                // Only the backups from the most recent install are kept
+                remove_dir_all(&aside_path)?;
+            }
+
+            if target_path.is_dir() {
+                debug!("Moving {:?} aside to {:?}", &target_path, &aside_path);
+                std::fs::rename(&target_path, &aside_path)?;
+                moved_aside.push((target_path, aside_path));
+            }
+        }
+
+        // Then move every new directory into place
+        for target in directories {
+            if let Some(progress) = progress {
+                progress.emit(ProgressEvent::SwappingDir { name: target.clone() });
+            }
+            let target_path = addons_path.join(target);
+            move_dir(&extracted_path.join(target), &target_path)
+                .map_err(|err| Error::install_context(format!("could not move `{}` into place", target), err))?;
+            installed.push(target_path);
+        }
+
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        restore_backups(&installed, &moved_aside)?;
+        return Err(Error::install_context("Install failed, the previous directories were restored", err));
+    }
+
+    if !backup {
+        for (_, aside_path) in moved_aside {
+            remove_dir_all(&aside_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails unless every one of `directories` was extracted to `extracted_path`.
+fn check_extracted(extracted_path: &Path, directories: &[String]) -> Result<()> {
+    let missing: Vec<&str> = directories
+        .iter()
+        .filter(|target| !extracted_path.join(target).is_dir())
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::install(format!(
+            "Extracted archive is missing {}, leaving the installed directories untouched",
+            missing.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// The directory in the addons directory that linked installs keep every
+/// release in, one directory per release. WoW only loads addons directly in
+/// the addons directory, so nothing in it is loaded twice.
+pub const STORE_DIR: &str = ".store";
+
+/// The store directory a linked install of `version` of `slug` goes in,
+/// e.g. `elvui-13.21`
+pub fn store_name(slug: &str, version: &str) -> String {
+    let version: String = version
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    format!("{}-{}", slug, version)
+}
+
+pub fn store_path(addons_path: &Path, name: &str) -> PathBuf {
+    addons_path.join(STORE_DIR).join(name)
+}
+
+/// Moves the extracted directories into the `store` and links each of them
+/// into the addons directory, as a unit like `swap_directories`.
+///
+/// A directory that is a link already only has its link replaced. One that
+/// isn't is moved aside, and kept as `<name>.bak` with `backup` set. If any
+/// step fails, the links, the directories moved aside and an earlier copy
+/// of the same store are all put back.
+fn link_directories(
+    addons_path: &Path,
+    extracted_path: &Path,
+    store: &str,
+    directories: &[String],
+    backup: bool,
+    progress: Option<&Progress>,
+) -> Result<()> {
+    let _section = CriticalSection::enter();
+    check_extracted(extracted_path, directories)?;
+
+    let store_path = store_path(addons_path, store);
+    let replaced_store = store_path.with_file_name(format!("{}.old", store));
+    if replaced_store.is_dir() {
+        remove_dir_all(&replaced_store)?;
+    }
+    if store_path.is_dir() {
+        std::fs::rename(&store_path, &replaced_store)?;
+    }
+    std::fs::create_dir_all(&store_path)?;
+
+    let mut linked: Vec<PathBuf> = Vec::new();
+    let mut unlinked: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut moved_aside: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    let result = (|| -> Result<()> {
+        for target in directories {
+            move_dir(&extracted_path.join(target), &store_path.join(target)).map_err(|err| {
+                Error::install_context(format!("could not move `{}` into {}", target, store_path.display()), err)
+            })?;
+        }
+
+        for target in directories {
+            if let Some(progress) = progress {
+                progress.emit(ProgressEvent::SwappingDir { name: target.clone() });
+            }
+            let target_path = addons_path.join(target);
+            if is_link(&target_path) {
+                let previous = std::fs::read_link(&target_path)?;
+                remove_link(&target_path)?;
+                unlinked.push((target_path.clone(), previous));
+            } else if target_path.is_dir() {
+                let aside_path = aside_path(addons_path, target, backup);
+                if aside_path.is_dir() {
+                    remove_dir_all(&aside_path)?;
+                }
+                debug!("Moving {:?} aside to {:?}", &target_path, &aside_path);
+                std::fs::rename(&target_path, &aside_path)?;
+                moved_aside.push((target_path.clone(), aside_path));
+            }
+
+            // Relative, so the links still work when the WoW directory moves
+            link_dir(&Path::new(STORE_DIR).join(store).join(target), &target_path)
+                .map_err(|err| Error::install_context(format!("could not link `{}` into place", target), err))?;
+            linked.push(target_path);
+        }
+
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        for link in &linked {
+            remove_link(link)?;
+        }
+        for (link, previous) in &unlinked {
+            debug!("Restoring the link {:?} to {:?}", link, previous);
+            link_dir(previous, link)?;
+        }
+        restore_backups(&[], &moved_aside)?;
+        remove_dir_all(&store_path)?;
+        if replaced_store.is_dir() {
+            std::fs::rename(&replaced_store, &store_path)?;
+        }
+        return Err(Error::install_context("Install failed, the previous directories were restored", err));
+    }
+
+    if !backup {
+        for (_, aside_path) in moved_aside {
+            remove_dir_all(&aside_path)?;
+        }
+    }
+    if replaced_store.is_dir() {
+        // Directories this install didn't replace may still be linked to
+        for entry in std::fs::read_dir(&replaced_store)? {
+            let entry = entry?;
+            let dest = store_path.join(entry.file_name());
+            if !dest.exists() {
+                std::fs::rename(entry.path(), dest)?;
+            }
+        }
+        remove_dir_all(&replaced_store)?;
+    }
+
+    Ok(())
+}
+
+/// Removes the stores in `names` that no directory in `addons_path` links
+/// into anymore, and the store directory once it is empty. Returns the
+/// stores removed.
+pub fn remove_stores(addons_path: &Path, names: &[String]) -> Result<Vec<String>> {
+    check_directory_names(names)?;
+    let mut removed = Vec::new();
+
+    for name in names {
+        let path = store_path(addons_path, name);
+        if !path.is_dir() {
+            continue;
+        }
+        if store_in_use(addons_path, &path) {
+            debug!("Keeping {:?}, a directory still links into it", path);
+            continue;
+        }
+        remove_dir_all(&path)?;
+        removed.push(name.clone());
+    }
+
+    // Only succeeds once it is empty
+    let _ = std::fs::remove_dir(addons_path.join(STORE_DIR));
+    Ok(removed)
+}
+
+/// Whether any entry of `addons_path` is a link into the store at `path`
+fn store_in_use(addons_path: &Path, path: &Path) -> bool {
+    let Ok(store) = std::fs::canonicalize(path) else { return false };
+    std::fs::read_dir(addons_path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| is_link(&entry.path()))
+        .filter_map(|entry| std::fs::canonicalize(entry.path()).ok())
+        .any(|linked| linked.starts_with(&store))
+}
+
+/// Points the directories of a linked install back at the store that was
+/// linked before, and records that release as installed. The two stores
+/// trade places, so rolling back again returns to where it started.
+pub fn rollback_links(addons_path: &Path, slug: &str) -> Result<Manifest> {
+    let mut manifest = read_manifest(addons_path, slug)?
+        .ok_or_else(|| Error::install(format!("{} is not installed in `{}`", slug, addons_path.display())))?;
+    let (Some(current), Some(previous)) = (manifest.store.clone(), manifest.previous_store.clone()) else {
+        return Err(Error::install(format!("No earlier release of {} in the store to roll back to", slug)));
+    };
+    check_directory_names(&manifest.directories)?;
+    check_directory_names(std::slice::from_ref(&previous))?;
+
+    let previous_path = store_path(addons_path, &previous);
+    let restorable: Vec<&String> = manifest.directories.iter().filter(|target| previous_path.join(target).is_dir()).collect();
+    if restorable.is_empty() {
+        return Err(Error::install(format!("`{}` is missing, there is nothing to roll back to", previous_path.display())));
+    }
+    // Links are all the rollback may replace
+    for target in &restorable {
+        let target_path = addons_path.join(target);
+        if target_path.symlink_metadata().is_ok() && !is_link(&target_path) {
+            return Err(Error::install(format!("Refusing to roll back `{}`, it is not linked into the store", target)));
+        }
+    }
+
+    let _section = CriticalSection::enter();
+    for target in &restorable {
+        let target_path = addons_path.join(target);
+        if is_link(&target_path) {
+            remove_link(&target_path)?;
+        }
+        link_dir(&Path::new(STORE_DIR).join(&previous).join(target), &target_path)
+            .map_err(|err| Error::install_context(format!("could not link `{}` to {}", target, previous), err))?;
+    }
+
+    let prefix = format!("{}-", slug);
+    manifest.version = previous.strip_prefix(&prefix).unwrap_or(&previous).to_string();
+    manifest.installed_at = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    // They were recorded for the release rolled back from
+    manifest.checksums = None;
+    manifest.store = Some(previous);
+    manifest.previous_store = Some(current);
+    write_manifest(addons_path, slug, &manifest)?;
+
+    Ok(manifest)
+}
+
+/// Like `swap_directories`, but every directory is swapped on its own: one
+/// that fails to move is put back as it was and the rest are still tried.
+/// Returns the directories that failed, with why.
+fn swap_directories_best_effort(
+    addons_path: &Path,
+    extracted_path: &Path,
+    directories: &[String],
+    backup: bool,
+    progress: Option<&Progress>,
+) -> Vec<(String, Error)> {
+    let _section = CriticalSection::enter();
+    let mut failed = Vec::new();
+
+    for target in directories {
+        if let Some(progress) = progress {
+            progress.emit(ProgressEvent::SwappingDir { name: target.clone() });
+        }
+        if let Err(err) = swap_directory(addons_path, extracted_path, target, backup) {
+            debug!("Unable to swap {}: {}", target, err);
+            failed.push((target.clone(), err));
+        }
+    }
+
+    failed
+}
+
+fn swap_directory(addons_path: &Path, extracted_path: &Path, target: &str, backup: bool) -> Result<()> {
+    let extracted = extracted_path.join(target);
+    if !extracted.is_dir() {
+        return Err(Error::install("missing from the extracted archive"));
+    }
+
+    let target_path = addons_path.join(target);
+    let aside_path = aside_path(addons_path, target, backup);
+    if aside_path.is_dir() {
+        remove_dir_all(&aside_path)?;
+    }
+
+    let moved_aside = target_path.is_dir();
+    if moved_aside {
+        debug!("Moving {:?} aside to {:?}", &target_path, &aside_path);
+        std::fs::rename(&target_path, &aside_path)?;
+    }
+
+    if let Err(err) = move_dir(&extracted, &target_path) {
+        if moved_aside {
+            debug!("Restoring {:?} from {:?}", &target_path, &aside_path);
+            std::fs::rename(&aside_path, &target_path)?;
+        }
+        return Err(err.into());
+    }
+
+    if moved_aside && !backup {
+        remove_dir_all(&aside_path)?;
+    }
+    Ok(())
+}
+
+/// Where the installed `target` is moved while its replacement goes in
+fn aside_path(addons_path: &Path, target: &str, backup: bool) -> PathBuf {
+    if backup {
+        backup_path(addons_path, target)
+    } else {
+        addons_path.join(format!("{}.old", target))
+    }
+}
+
+fn restore_backups(installed: &[PathBuf], backups: &[(PathBuf, PathBuf)]) -> Result<()> {
+    for target_path in installed {
+        remove_dir_all(target_path)?;
+    }
+
+    for (target_path, backup_path) in backups {
+        debug!("Restoring {:?} from {:?}", target_path, backup_path);
+        std::fs::rename(backup_path, target_path)
+            .map_err(|err| Error::install_context(format!("could not restore backup `{}`", backup_path.display()), err))?;
+    }
+
+    Ok(())
+}
+
+/// The entries of `directories` that have a backup to restore.
+pub fn find_backups(addons_path: &Path, directories: &[String]) -> Vec<String> {
+    directories
+        .iter()
+        .filter(|target| backup_path(addons_path, target).is_dir())
+        .cloned()
+        .collect()
+}
+
+/// Replaces each of `directories` with its backup, returning the ones that
+/// were restored. Directories without a backup are left as they are, and
+/// it is an error for none of them to have one.
+pub fn rollback_directories(addons_path: &Path, directories: &[String]) -> Result<Vec<String>> {
+    check_directory_names(directories)?;
+    let restorable = find_backups(addons_path, directories);
+    if restorable.is_empty() {
+        return Err(Error::install(format!("No backups found in `{}`", addons_path.display())));
+    }
+
+    let _section = CriticalSection::enter();
+
+    for target in &restorable {
+        let target_path = addons_path.join(target);
+        let backup_path = backup_path(addons_path, target);
+
+        if target_path.is_dir() {
+            remove_dir_all(&target_path)?;
+        }
+        std::fs::rename(&backup_path, &target_path)
+            .map_err(|err| Error::install_context(format!("could not restore backup `{}`", backup_path.display()), err))?;
+    }
+
+    Ok(restorable)
+}
+
+/// The directories in `recorded`, as listed by an earlier install's
+/// manifest, that are still present but are no longer among `directories`.
+/// Only ever directories this tool installed, never other addons.
+pub fn orphaned_directories(addons_path: &Path, recorded: &[String], directories: &[String]) -> Vec<String> {
+    recorded
+        .iter()
+        .filter(|target| !directories.contains(target) && addons_path.join(target).is_dir())
+        .cloned()
+        .collect()
+}
+
+/// Removes each of `directories` under the addons path, returning the ones
+/// that were actually present. Missing directories are skipped.
+pub fn uninstall_directories(addons_path: &Path, directories: &[String]) -> Result<Vec<String>> {
+    check_directory_names(directories)?;
+    let mut removed = Vec::new();
+
+    for target in directories {
+        let target_path = addons_path.join(target);
+        if is_link(&target_path) {
+            remove_link(&target_path)?;
+        } else if target_path.is_dir() {
+            remove_dir_all(&target_path)?;
+        } else {
+            continue;
+        }
+        removed.push(target.clone());
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_addon, read_marker, sample_metadata, write_zip};
+
+    /// A `PreparedInstall` of `metadata` extracted to `tempdir`'s `elvui`,
+    /// with every option off
+    fn prepared(addons: &Path, tempdir: TempDir, metadata: ElvuiMetadata) -> PreparedInstall {
+        PreparedInstall {
+            addons_path: addons.to_path_buf(),
+            extracted_path: tempdir.path().join("elvui"),
+            tempdir,
+            metadata,
+            downloaded_from: String::new(),
+            cached_archive: None,
+            archive_entries: Vec::new(),
+            backup: false,
+            keep_temp: false,
+            pin: None,
+            retain: Vec::new(),
+            checksums: false,
+            best_effort: false,
+            overlay: false,
+            link: false,
+            progress: None,
+        }
+    }
+
+    #[test]
+    fn dry_run_leaves_addons_untouched() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::create_dir(addons.path().join("ElvUI")).unwrap();
+
+        let options = InstallOptions { backup: true, ..Default::default() };
+        let plan = plan_install(addons.path(), &sample_metadata(), &options).unwrap();
+        assert!(plan.contains(&Operation::Backup {
+            from: addons.path().join("ElvUI"),
+            to: addons.path().join("ElvUI.bak"),
+        }));
+        assert!(addons.path().join("ElvUI").is_dir());
+        assert!(!addons.path().join("ElvUI.bak").exists());
+        assert!(!addons.path().join("ElvUI_Options").exists());
+    }
+
+    #[test]
+    fn refuses_directories_outside_addons() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+
+        for target in ["../../WTF", "WTF", "ElvUI/../..", "/etc", ""] {
+            let mut metadata = sample_metadata();
+            metadata.directories = vec![target.to_string()];
+
+            let err = plan_install(addons.path(), &metadata, &InstallOptions::default()).unwrap_err();
+            assert!(err.to_string().starts_with("Refusing to touch"), "{}", target);
+            assert!(uninstall_directories(addons.path(), &metadata.directories).is_err());
+        }
+    }
+
+    #[test]
+    fn finds_lua_files_missing_from_new_release() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted_path = tempdir.path().join("elvui");
+        create_addon(addons.path(), "ElvUI", "old");
+        std::fs::create_dir(addons.path().join("ElvUI/Settings")).unwrap();
+        std::fs::write(addons.path().join("ElvUI/Core.lua"), "").unwrap();
+        std::fs::write(addons.path().join("ElvUI/Settings/Profile.lua"), "").unwrap();
+        create_addon(&extracted_path, "ElvUI", "new");
+        std::fs::write(extracted_path.join("ElvUI/Core.lua"), "").unwrap();
+
+        let prepared = PreparedInstall { backup: true, ..prepared(addons.path(), tempdir, sample_metadata()) };
+        assert_eq!(prepared.unshipped_lua_files(), vec![addons.path().join("ElvUI/Settings/Profile.lua")]);
+    }
+
+    #[test]
+    fn refuses_metadata_without_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let metadata = ElvuiMetadata { directories: Vec::new(), ..sample_metadata() };
+
+        let err = prepare_install(&Client::new(), addons.path(), metadata.clone(), &InstallOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("no addon directories"), "{}", err);
+        assert!(plan_install(addons.path(), &metadata, &InstallOptions::default()).is_err());
+    }
+
+    #[test]
+    fn installs_from_a_local_archive() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let downloads = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let archive = downloads.path().join("elvui-13.22.zip");
+        write_zip(&archive, &[
+            ("ElvUI/ElvUI_Mainline.toc", "## Version: 13.22\n"),
+            ("ElvUI_Options/marker", "new"),
+        ]);
+        let options = InstallOptions { archive: Some(archive.clone()), ..Default::default() };
+
+        let mut prepared = prepare_install(&Client::new(), addons.path(), sample_metadata(), &options).unwrap();
+        assert_eq!(prepared.downloaded_from(), archive.display().to_string());
+        assert_eq!(prepared.use_release_version(Flavor::Retail).unwrap(), "13.22");
+        prepared.commit().unwrap();
+
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "new");
+        assert!(archive.is_file());
+        let manifest = crate::manifest::read_manifest(addons.path(), "elvui").unwrap().unwrap();
+        assert_eq!(manifest.version, "13.22");
+    }
+
+    #[test]
+    fn reuses_archive_downloaded_earlier() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let cache = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        // Unreachable, so only the cached archive can be installed
+        let metadata = ElvuiMetadata { url: "http://127.0.0.1:9/elvui.zip".to_string(), ..sample_metadata() };
+        let cached = cached_archive_path(cache.path(), &metadata);
+        assert_eq!(cached, cache.path().join("elvui-13.21.zip"));
+        write_zip(&cached, &[("ElvUI/marker", "cached"), ("ElvUI_Options/marker", "cached")]);
+        let options = InstallOptions { download_cache: Some(cache.path().to_path_buf()), ..Default::default() };
+
+        let prepared = prepare_install(&Client::new(), addons.path(), metadata.clone(), &options).unwrap();
+        assert!(prepared.reused_archive());
+        prepared.commit().unwrap();
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "cached");
+        assert!(!cached.exists());
+
+        // A broken archive is thrown away and downloaded again
+        std::fs::write(&cached, "not a zip").unwrap();
+        assert!(prepare_install(&Client::new(), addons.path(), metadata, &options).is_err());
+        assert!(!cached.exists());
+    }
+
+    #[test]
+    fn overridden_directories_must_be_in_the_archive() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let downloads = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        let archive = downloads.path().join("elvui-fork.zip");
+        write_zip(&archive, &[("ElvUI/marker", "new")]);
+        let options = InstallOptions { archive: Some(archive), ..Default::default() };
+        let metadata = ElvuiMetadata { directories: vec!["ElvUI".to_string(), "ElvUI_Fork".to_string()], ..sample_metadata() };
+
+        let err = prepare_install(&Client::new(), addons.path(), metadata, &options).unwrap_err();
+        assert!(err.to_string().contains("missing expected directories: ElvUI_Fork"), "{}", err);
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+    }
+
+    #[test]
+    fn linked_installs_roll_back_by_relinking() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "unlinked");
+        let commit = |version: &str| {
+            let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+            let extracted_path = tempdir.path().join("elvui");
+            let metadata = ElvuiMetadata { version: version.to_string(), ..sample_metadata() };
+            for target in &metadata.directories {
+                create_addon(&extracted_path, target, version);
+            }
+            let prepared = PreparedInstall { backup: true, link: true, ..prepared(addons.path(), tempdir, metadata) };
+            prepared.commit().unwrap();
+        };
+
+        commit("13.20");
+        assert!(is_link(&addons.path().join("ElvUI")));
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "13.20");
+        assert_eq!(read_marker(addons.path(), "ElvUI.bak"), "unlinked");
+
+        commit("13.21");
+        commit("13.22");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "13.22");
+        // Only the store linked before is kept
+        assert!(!store_path(addons.path(), "elvui-13.20").exists());
+        let manifest = crate::manifest::read_manifest(addons.path(), "elvui").unwrap().unwrap();
+        assert_eq!(manifest.store.as_deref(), Some("elvui-13.22"));
+        assert_eq!(manifest.previous_store.as_deref(), Some("elvui-13.21"));
+
+        let manifest = rollback_links(addons.path(), "elvui").unwrap();
+        assert_eq!(manifest.version, "13.21");
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "13.21");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "13.21");
+
+        assert_eq!(uninstall_directories(addons.path(), &manifest.directories).unwrap().len(), 2);
+        let stores = vec!["elvui-13.21".to_string(), "elvui-13.22".to_string()];
+        assert_eq!(remove_stores(addons.path(), &stores).unwrap(), stores);
+        assert!(!addons.path().join(STORE_DIR).exists());
+    }
+
+    #[test]
+    fn missing_temp_dir_is_explained() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let temp_dir = addons.path().join("missing");
+        let options = InstallOptions { temp_dir: Some(temp_dir.clone()), ..Default::default() };
+
+        let err = prepare_install(&Client::new(), addons.path(), sample_metadata(), &options).unwrap_err();
+        assert!(err.to_string().contains(&temp_dir.display().to_string()), "{}", err);
+    }
+
+    #[test]
+    fn removes_read_only_files() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        let marker = addons.path().join("ElvUI/marker");
+        let mut permissions = std::fs::metadata(&marker).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&marker, permissions).unwrap();
+
+        clear_readonly(&addons.path().join("ElvUI")).unwrap();
+        assert!(!std::fs::metadata(&marker).unwrap().permissions().readonly());
+
+        remove_dir_all(&addons.path().join("ElvUI")).unwrap();
+        assert!(!addons.path().join("ElvUI").exists());
+    }
+
+    #[test]
+    fn swap_keeps_backups_on_success() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        create_addon(extracted.path(), "ElvUI", "new");
+        create_addon(extracted.path(), "ElvUI_Options", "new");
+
+        let directories = sample_metadata().directories;
+        swap_directories(addons.path(), extracted.path(), &directories, true, None).unwrap();
+
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "new");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "new");
+        assert_eq!(read_marker(addons.path(), "ElvUI.bak"), "old");
+        assert!(!backup_path(addons.path(), "ElvUI_Options").exists());
+    }
+
+    #[test]
+    fn swap_restores_backups_on_failure() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        create_addon(addons.path(), "ElvUI_Options", "old");
+        create_addon(extracted.path(), "ElvUI", "new");
+
+        let directories = sample_metadata().directories;
+        let result = swap_directories(addons.path(), extracted.path(), &directories, true, None);
+
+        assert!(matches!(result, Err(Error::Install { .. })));
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "old");
+        assert!(!backup_path(addons.path(), "ElvUI").exists());
+    }
+
+    #[test]
+    fn swap_checks_extracted_directories_first() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        create_addon(addons.path(), "ElvUI_Options", "old");
+        create_addon(extracted.path(), "ElvUI", "new");
+
+        let directories = sample_metadata().directories;
+        let err = swap_directories(addons.path(), extracted.path(), &directories, false, None).unwrap_err();
+
+        assert!(err.to_string().contains("missing ElvUI_Options"));
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+        assert!(!addons.path().join("ElvUI.old").exists());
+        assert_eq!(read_marker(extracted.path(), "ElvUI"), "new");
+    }
+
+    #[test]
+    fn swap_restores_backups_when_a_move_fails() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        // A file in the way of a directory makes moving it into place fail
+        std::fs::write(addons.path().join("ElvUI_Options"), "not a directory").unwrap();
+        create_addon(extracted.path(), "ElvUI", "new");
+        create_addon(extracted.path(), "ElvUI_Options", "new");
+
+        let directories = sample_metadata().directories;
+        let result = swap_directories(addons.path(), extracted.path(), &directories, true, None);
+
+        assert!(matches!(result, Err(Error::Install { .. })));
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+        assert!(!backup_path(addons.path(), "ElvUI").exists());
+    }
+
+    #[test]
+    fn best_effort_swap_updates_the_directories_it_can() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        std::fs::write(addons.path().join("ElvUI_Options"), "not a directory").unwrap();
+        create_addon(extracted.path(), "ElvUI", "new");
+        create_addon(extracted.path(), "ElvUI_Options", "new");
+
+        let directories = sample_metadata().directories;
+        let failed = swap_directories_best_effort(addons.path(), extracted.path(), &directories, true, None);
+
+        assert_eq!(failed.iter().map(|(target, _)| target.as_str()).collect::<Vec<_>>(), vec!["ElvUI_Options"]);
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "new");
+        assert_eq!(read_marker(addons.path(), "ElvUI.bak"), "old");
+        assert!(addons.path().join("ElvUI_Options").is_file());
+    }
+
+    #[test]
+    fn swap_without_backup_is_atomic() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        create_addon(addons.path(), "ElvUI_Options", "old");
+        create_addon(extracted.path(), "ElvUI", "new");
+
+        let directories = sample_metadata().directories;
+        let result = swap_directories(addons.path(), extracted.path(), &directories, false, None);
+
+        assert!(result.is_err());
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "old");
+        assert!(!addons.path().join("ElvUI.old").exists());
+    }
+
+    #[test]
+    fn swap_without_backup_removes_old_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "old");
+        create_addon(extracted.path(), "ElvUI", "new");
+        create_addon(extracted.path(), "ElvUI_Options", "new");
+
+        let directories = sample_metadata().directories;
+        swap_directories(addons.path(), extracted.path(), &directories, false, None).unwrap();
+
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "new");
+        assert!(!addons.path().join("ElvUI.old").exists());
+        assert!(!backup_path(addons.path(), "ElvUI").exists());
+    }
+
+    #[test]
+    fn copies_directories_recursively() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(dir.path(), "from/ElvUI/Libraries", "lib");
+        create_addon(dir.path(), "from/ElvUI", "core");
+
+        copy_dir_all(&dir.path().join("from"), &dir.path().join("to")).unwrap();
+
+        assert_eq!(read_marker(dir.path(), "to/ElvUI"), "core");
+        assert_eq!(read_marker(dir.path(), "to/ElvUI/Libraries"), "lib");
+    }
+
+    #[test]
+    fn rollback_restores_partial_backup_set() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "new");
+        create_addon(addons.path(), "ElvUI.bak", "old");
+        create_addon(addons.path(), "ElvUI_Options", "new");
+
+        let directories = sample_metadata().directories;
+        let restored = rollback_directories(addons.path(), &directories).unwrap();
+
+        assert_eq!(restored, vec!["ElvUI".to_string()]);
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "new");
+        assert!(!backup_path(addons.path(), "ElvUI").exists());
+    }
+
+    #[test]
+    fn rollback_without_backups_fails() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "new");
+
+        let directories = sample_metadata().directories;
+        assert!(rollback_directories(addons.path(), &directories).is_err());
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "new");
+    }
+
+    #[test]
+    fn fills_in_mirror_urls() {
+        let metadata = sample_metadata();
+        assert_eq!(
+            mirror_url("https://mirror.example/{slug}/{slug}-{version}.zip", &metadata),
+            "https://mirror.example/elvui/elvui-13.21.zip"
+        );
+        assert_eq!(mirror_url("https://mirror.example/latest.zip", &metadata), "https://mirror.example/latest.zip");
+    }
+
+    #[test]
+    fn finds_orphaned_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "installed");
+        create_addon(addons.path(), "ElvUI_OptionsUI", "installed");
+        create_addon(addons.path(), "Details", "installed");
+
+        let recorded = vec!["ElvUI".to_string(), "ElvUI_OptionsUI".to_string(), "ElvUI_Libraries".to_string()];
+        let orphans = orphaned_directories(addons.path(), &recorded, &sample_metadata().directories);
+
+        assert_eq!(orphans, vec!["ElvUI_OptionsUI".to_string()]);
+    }
+
+    #[test]
+    fn commit_keeps_retained_directories_in_manifest() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted_path = tempdir.path().join("elvui");
+        let metadata = sample_metadata();
+        for target in &metadata.directories {
+            create_addon(&extracted_path, target, "new");
+        }
+
+        let prepared = PreparedInstall {
+            retain: vec!["ElvUI_OptionsUI".to_string(), "ElvUI".to_string()],
+            ..prepared(addons.path(), tempdir, metadata.clone())
+        };
+        prepared.commit().unwrap();
+
+        let manifest = crate::manifest::read_manifest(addons.path(), &metadata.slug).unwrap().unwrap();
+        let mut expected = metadata.directories;
+        expected.push("ElvUI_OptionsUI".to_string());
+        assert_eq!(manifest.directories, expected);
+    }
+
+    #[test]
+    fn commit_records_checksums_when_asked() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted_path = tempdir.path().join("elvui");
+        let metadata = sample_metadata();
+        for target in &metadata.directories {
+            create_addon(&extracted_path, target, "new");
+        }
+
+        let prepared = PreparedInstall { checksums: true, ..prepared(addons.path(), tempdir, metadata.clone()) };
+        prepared.commit().unwrap();
+
+        let manifest = crate::manifest::read_manifest(addons.path(), &metadata.slug).unwrap().unwrap();
+        let checksums = manifest.checksums.unwrap();
+        assert_eq!(checksums.keys().collect::<Vec<_>>(), vec!["ElvUI", "ElvUI_Options"]);
+        assert!(checksums["ElvUI"].contains_key(Path::new("marker")));
+    }
+
+    #[test]
+    fn overlay_commit_keeps_unshipped_files() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted_path = tempdir.path().join("elvui");
+        let metadata = sample_metadata();
+        for target in &metadata.directories {
+            create_addon(&extracted_path, target, "new");
+        }
+        create_addon(addons.path(), "ElvUI", "old");
+        std::fs::create_dir(addons.path().join("ElvUI/Custom")).unwrap();
+        std::fs::write(addons.path().join("ElvUI/Custom/Config.lua"), "mine").unwrap();
+
+        let prepared = PreparedInstall { checksums: true, overlay: true, ..prepared(addons.path(), tempdir, metadata.clone()) };
+        assert!(prepared.unshipped_lua_files().is_empty());
+        prepared.commit().unwrap();
+
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "new");
+        assert_eq!(std::fs::read_to_string(addons.path().join("ElvUI/Custom/Config.lua")).unwrap(), "mine");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "new");
+        let manifest = crate::manifest::read_manifest(addons.path(), &metadata.slug).unwrap().unwrap();
+        assert!(!manifest.checksums.unwrap()["ElvUI"].contains_key(Path::new("Custom/Config.lua")));
+    }
+
+    #[test]
+    fn best_effort_commit_records_stale_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::write(addons.path().join("ElvUI_Options"), "not a directory").unwrap();
+        let commit = || {
+            let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+            let metadata = sample_metadata();
+            for target in &metadata.directories {
+                create_addon(&tempdir.path().join("elvui"), target, "new");
+            }
+            PreparedInstall { best_effort: true, ..prepared(addons.path(), tempdir, metadata) }.commit()
+        };
+
+        assert!(matches!(commit(), Err(Error::PartialInstall { .. })));
+        let manifest = crate::manifest::read_manifest(addons.path(), "elvui").unwrap().unwrap();
+        assert_eq!(manifest.stale, vec!["ElvUI_Options".to_string()]);
+
+        std::fs::remove_file(addons.path().join("ElvUI_Options")).unwrap();
+        commit().unwrap();
+        let manifest = crate::manifest::read_manifest(addons.path(), "elvui").unwrap().unwrap();
+        assert!(manifest.stale.is_empty());
+    }
+
+    #[test]
+    fn commit_reports_progress() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted_path = tempdir.path().join("elvui");
+        let metadata = sample_metadata();
+        for target in &metadata.directories {
+            create_addon(&extracted_path, target, "new");
+        }
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let collected = Arc::clone(&events);
+        let prepared = PreparedInstall {
+            progress: Some(Progress::new(move |event| collected.lock().unwrap().push(event))),
+            ..prepared(addons.path(), tempdir, metadata)
+        };
+        prepared.commit().unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            ProgressEvent::SwappingDir { name: "ElvUI".to_string() },
+            ProgressEvent::SwappingDir { name: "ElvUI_Options".to_string() },
+            ProgressEvent::Done,
+        ]);
+    }
+
+    #[test]
+    fn restricted_commit_only_replaces_given_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let extracted_path = tempdir.path().join("elvui");
+        let metadata = sample_metadata();
+        for target in &metadata.directories {
+            create_addon(addons.path(), target, "old");
+            create_addon(&extracted_path, target, "new");
+        }
+
+        let mut prepared = prepared(addons.path(), tempdir, metadata.clone());
+        assert_eq!(prepared.verify().unwrap().iter().filter(|diff| !diff.is_clean()).count(), 2);
+        prepared.restrict_to(&["ElvUI_Options".to_string()]);
+        prepared.commit().unwrap();
+
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "old");
+        assert_eq!(read_marker(addons.path(), "ElvUI_Options"), "new");
+        let manifest = crate::manifest::read_manifest(addons.path(), &metadata.slug).unwrap().unwrap();
+        assert_eq!(manifest.directories, vec!["ElvUI_Options".to_string(), "ElvUI".to_string()]);
+    }
+
+    #[test]
+    fn failed_commit_keeps_temp_when_asked() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let tempdir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let temp_path = tempdir.path().to_path_buf();
+
+        // Nothing was extracted, so the swap fails
+        let prepared = PreparedInstall { keep_temp: true, ..prepared(addons.path(), tempdir, sample_metadata()) };
+        let err = prepared.commit().unwrap_err();
+
+        assert!(err.to_string().contains(&temp_path.display().to_string()));
+        assert!(temp_path.is_dir());
+        std::fs::remove_dir_all(&temp_path).unwrap();
+    }
+
+    #[test]
+    fn uninstall_skips_missing_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "installed");
+
+        let directories = sample_metadata().directories;
+        let removed = uninstall_directories(addons.path(), &directories).unwrap();
+
+        assert_eq!(removed, vec!["ElvUI".to_string()]);
+        assert!(!addons.path().join("ElvUI").exists());
+    }
+}
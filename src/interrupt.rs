@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{Error, Result};
+
+static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+static CRITICAL_SECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Asks every running step to stop at the next chance, e.g. on Ctrl-C.
+/// Returns how many times an interruption has been requested so far.
+///
+/// Nothing is stopped from outside: downloads, retries and extraction
+/// check the flag and fail with `Error::Interrupted`, so temporary files
+/// are cleaned up as the error unwinds. Swapping directories always
+/// finishes first.
+pub fn request() -> usize {
+    REQUESTS.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+pub fn is_requested() -> bool {
+    REQUESTS.load(Ordering::SeqCst) > 0
+}
+
+/// Fails with `Error::Interrupted` once an interruption was requested.
+pub fn check() -> Result<()> {
+    if is_requested() {
+        return Err(Error::Interrupted);
+    }
+    Ok(())
+}
+
+/// Whether directories are being swapped right now, which must not be cut
+/// short or the addons directory is left half updated
+pub fn in_critical_section() -> bool {
+    CRITICAL_SECTIONS.load(Ordering::SeqCst) > 0
+}
+
+/// Marks a step that must run to completion, until it is dropped.
+pub(crate) struct CriticalSection(());
+
+impl CriticalSection {
+    pub(crate) fn enter() -> Self {
+        CRITICAL_SECTIONS.fetch_add(1, Ordering::SeqCst);
+        CriticalSection(())
+    }
+}
+
+impl Drop for CriticalSection {
+    fn drop(&mut self) {
+        CRITICAL_SECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `request` isn't tested: the flag is global and would interrupt every
+    // other test running in the process
+    #[test]
+    fn critical_section_lasts_until_dropped() {
+        let section = CriticalSection::enter();
+        assert!(in_critical_section());
+        drop(section);
+    }
+}
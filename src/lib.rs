@@ -0,0 +1,35 @@
+//! Installs / Updates ElvUI
+//!
+//! The `elvui-manager` binary is a thin wrapper around this library, which
+//! can also be embedded by other addon managers. Library functions return
+//! errors instead of logging them, and only emit `debug!` traces. The
+//! exception is `commands`, the binary's commands, which report their
+//! progress at `info!` and `warn!` and leave printing results to the caller.
+
+pub mod archive;
+pub mod cache;
+pub mod client;
+pub mod commands;
+pub mod debug_dump;
+mod error;
+pub mod hooks;
+pub mod http;
+pub mod install;
+pub mod interrupt;
+mod link;
+pub mod lock;
+pub mod manifest;
+pub mod metadata;
+pub mod notify;
+pub mod paths;
+pub mod toc;
+pub mod verify;
+pub mod version;
+
+#[cfg(test)]
+mod test_support;
+
+pub use error::{Error, Result};
+pub use install::{install, plan_install, prepare_install, InstallOptions, Operation, PreparedInstall, Progress, ProgressEvent};
+pub use metadata::{fetch_metadata, ElvuiMetadata, Flavor, Source};
+pub use toc::{fetch_addon_version, fetch_installed_version, parse_toc, TocMetadata};
@@ -0,0 +1,88 @@
+use std::io;
+use std::path::Path;
+
+/// Whether `path` itself is a symlink or, on Windows, a junction
+pub(crate) fn is_link(path: &Path) -> bool {
+    path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Creates `link` pointing at the directory `target`, which may be relative
+/// to the directory `link` is in.
+#[cfg(unix)]
+pub(crate) fn link_dir(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Creates `link` pointing at the directory `target`, which may be relative
+/// to the directory `link` is in. Symlinks need Developer Mode or an
+/// administrator, so without either a junction is created instead.
+#[cfg(windows)]
+pub(crate) fn link_dir(target: &Path, link: &Path) -> io::Result<()> {
+    // ERROR_PRIVILEGE_NOT_HELD
+    const PRIVILEGE_NOT_HELD: i32 = 1314;
+
+    match std::os::windows::fs::symlink_dir(target, link) {
+        Err(err) if err.raw_os_error() == Some(PRIVILEGE_NOT_HELD) => create_junction(target, link),
+        result => result,
+    }
+}
+
+/// Junctions must point at an absolute path, without the `\\?\` prefix
+/// `read_link` returns them with.
+#[cfg(windows)]
+fn create_junction(target: &Path, link: &Path) -> io::Result<()> {
+    let target = match link.parent() {
+        Some(parent) if target.is_relative() => std::path::absolute(parent.join(target))?,
+        _ => target.to_path_buf(),
+    };
+    let target = target.to_string_lossy();
+    let target = target.strip_prefix(r"\\?\").unwrap_or(&target);
+
+    let status = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(link)
+        .arg(target)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("mklink /J failed with {}", status)));
+    }
+    Ok(())
+}
+
+/// Removes the link itself, never what it points at.
+#[cfg(unix)]
+pub(crate) fn remove_link(link: &Path) -> io::Result<()> {
+    std::fs::remove_file(link)
+}
+
+/// Removes the link itself, never what it points at. Directory symlinks
+/// and junctions are both removed like an empty directory.
+#[cfg(windows)]
+pub(crate) fn remove_link(link: &Path) -> io::Result<()> {
+    std::fs::remove_dir(link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_addon, read_marker};
+    use tempfile::Builder;
+
+    #[test]
+    fn links_and_unlinks_directories() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(&addons.path().join(".store/elvui-13.21"), "ElvUI", "linked");
+        let link = addons.path().join("ElvUI");
+
+        link_dir(Path::new(".store/elvui-13.21/ElvUI"), &link).unwrap();
+        assert!(is_link(&link));
+        assert_eq!(read_marker(addons.path(), "ElvUI"), "linked");
+
+        remove_link(&link).unwrap();
+        assert!(!link.exists());
+        assert_eq!(read_marker(&addons.path().join(".store/elvui-13.21"), "ElvUI"), "linked");
+    }
+}
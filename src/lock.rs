@@ -0,0 +1,107 @@
+use fs2::FileExt;
+use log::debug;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+pub const LOCK_FILE: &str = ".elvui-manager.lock";
+
+/// An exclusive lock on an addons directory, held until it is dropped.
+///
+/// The lock is an OS file lock on `.elvui-manager.lock`, so it is released
+/// by the OS when a run crashes. A lock file left behind is never stale: it
+/// is only held while the process that locked it is alive, and the next run
+/// simply takes it over.
+#[derive(Debug)]
+pub struct AddonsLock {
+    file: File,
+}
+
+impl AddonsLock {
+    /// Locks `addons_path`, either failing right away with `Error::Locked`
+    /// when another run holds it or, with `wait`, blocking until it is freed.
+    pub fn acquire(addons_path: &Path, wait: bool) -> Result<AddonsLock> {
+        let path = addons_path.join(LOCK_FILE);
+        // Not truncated on open, so the pid of the current holder stays readable
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+        if let Err(err) = file.try_lock_exclusive() {
+            if err.kind() != fs2::lock_contended_error().kind() {
+                return Err(err.into());
+            }
+            if !wait {
+                return Err(Error::Locked { pid: read_pid(&mut file), path });
+            }
+
+            debug!("Waiting for the lock on {:?}", &path);
+            file.lock_exclusive()?;
+        }
+
+        // Recorded only to tell users which process is holding the lock
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        debug!("Locked {:?}", &path);
+
+        Ok(AddonsLock { file })
+    }
+}
+
+impl Drop for AddonsLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn second_lock_fails_until_released() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let lock = AddonsLock::acquire(addons.path(), false).unwrap();
+
+        let err = AddonsLock::acquire(addons.path(), false).unwrap_err();
+        assert!(matches!(err, Error::Locked { pid: Some(pid), .. } if pid == std::process::id()));
+        assert!(err.to_string().contains("is in use by another elvui-manager"));
+
+        drop(lock);
+        assert!(AddonsLock::acquire(addons.path(), false).is_ok());
+    }
+
+    #[test]
+    fn leftover_lock_file_is_taken_over() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::write(addons.path().join(LOCK_FILE), "4294967295").unwrap();
+
+        assert!(AddonsLock::acquire(addons.path(), false).is_ok());
+        assert_eq!(
+            std::fs::read_to_string(addons.path().join(LOCK_FILE)).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+
+    #[test]
+    fn wait_blocks_until_released() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let lock = AddonsLock::acquire(addons.path(), false).unwrap();
+
+        let path = addons.path().to_path_buf();
+        let waiter = std::thread::spawn(move || AddonsLock::acquire(&path, true).map(|_| ()));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!waiter.is_finished());
+
+        drop(lock);
+        assert!(waiter.join().unwrap().is_ok());
+    }
+}
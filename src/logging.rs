@@ -0,0 +1,155 @@
+use log::{Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
+
+/// Sends every record env_logger accepts to stderr as usual, and also
+/// appends it to a log file when one is configured.
+pub struct TeeLogger {
+    stderr: env_logger::Logger,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl TeeLogger {
+    pub fn new(stderr: env_logger::Logger, file: Option<RotatingFile>) -> Self {
+        TeeLogger { stderr, file: file.map(Mutex::new) }
+    }
+
+    pub fn init(self) {
+        log::set_max_level(self.stderr.filter());
+        log::set_boxed_logger(Box::new(self)).expect("the logger is only set once");
+    }
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.stderr.matches(record) {
+            return;
+        }
+        self.stderr.log(record);
+
+        if let Some(file) = &self.file {
+            // Lines in the file are always plain and timestamped, whatever `--color` says
+            let line = format!(
+                "[{} {:<5} {}] {}\n",
+                humantime::format_rfc3339_seconds(SystemTime::now()),
+                record.level(),
+                record.target(),
+                record.args(),
+            );
+            // A full disk shouldn't take the run down with it, so write errors are dropped
+            let _ = file.lock().unwrap_or_else(PoisonError::into_inner).write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap_or_else(PoisonError::into_inner).file.flush();
+        }
+    }
+}
+
+/// A log file that is moved to `<path>.1` once it grows past `max_size`,
+/// shifting older ones up to `<path>.<keep>` and deleting the rest.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, keep: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile { path: path.to_path_buf(), file, size, max_size, keep })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        remove_if_exists(&rotated_path(&self.path, self.keep))?;
+        for n in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.is_file() {
+                std::fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+
+        if self.keep == 0 {
+            remove_if_exists(&self.path)?;
+        } else {
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn rotates_and_keeps_limited_history() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let path = dir.path().join("elvui-manager.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "third\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "second\n");
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn appends_to_existing_log() {
+        let dir = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let path = dir.path().join("elvui-manager.log");
+        std::fs::write(&path, "earlier run\n").unwrap();
+
+        let mut file = RotatingFile::open(&path, 1024, 2).unwrap();
+        file.write_line("this run\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "earlier run\nthis run\n");
+        assert!(!rotated_path(&path, 1).exists());
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::metadata::ELVUI_SLUG;
+use crate::verify::FileHashes;
+
+/// The manifest of ElvUI itself. Other addons get `.elvui-manager-<slug>.json`.
+pub const MANIFEST_FILE: &str = ".elvui-manager.json";
+
+/// Records what the last install placed in the addons directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub version: String,
+    pub installed_at: String,
+    pub directories: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<String>,
+    /// Whether the installed release is a beta or alpha
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prerelease: bool,
+    /// The hashes of the installed files per directory, when the install
+    /// was asked to record them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<BTreeMap<String, FileHashes>>,
+    /// With `InstallOptions::link`, the directory in `STORE_DIR` the
+    /// installed directories link to, e.g. `elvui-13.21`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// The one linked before `store`, kept for `rollback_links`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_store: Option<String>,
+    /// Directories a best-effort install couldn't replace, which still hold
+    /// an earlier release than `version`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stale: Vec<String>,
+}
+
+pub fn manifest_path(addons_path: &Path, slug: &str) -> PathBuf {
+    if slug == ELVUI_SLUG {
+        addons_path.join(MANIFEST_FILE)
+    } else {
+        addons_path.join(format!(".elvui-manager-{}.json", slug))
+    }
+}
+
+pub fn read_manifest(addons_path: &Path, slug: &str) -> Result<Option<Manifest>> {
+    let path = manifest_path(addons_path, slug);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(&path)?;
+    match serde_json::from_str(&content) {
+        Ok(manifest) => Ok(Some(manifest)),
+        Err(source) => Err(Error::Manifest { path, source }),
+    }
+}
+
+/// Writes the manifest to a temp file next to it and renames it into place,
+/// so readers never see a partially written manifest.
+pub fn write_manifest(addons_path: &Path, slug: &str, manifest: &Manifest) -> Result<()> {
+    let mut file = tempfile::NamedTempFile::new_in(addons_path)?;
+    serde_json::to_writer_pretty(&mut file, manifest).map_err(std::io::Error::from)?;
+    file.persist(manifest_path(addons_path, slug)).map_err(|err| err.error)?;
+
+    Ok(())
+}
+
+pub fn remove_manifest(addons_path: &Path, slug: &str) -> Result<()> {
+    std::fs::remove_file(manifest_path(addons_path, slug))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_metadata;
+    use tempfile::Builder;
+
+    #[test]
+    fn manifest_round_trips() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        assert_eq!(read_manifest(addons.path(), ELVUI_SLUG).unwrap(), None);
+
+        let manifest = Manifest {
+            version: "13.21".to_string(),
+            installed_at: "2023-06-01T12:00:00Z".to_string(),
+            directories: sample_metadata().directories,
+            pinned: Some("13.21".to_string()),
+            prerelease: true,
+            checksums: Some(BTreeMap::from([(
+                "ElvUI".to_string(),
+                FileHashes::from([(PathBuf::from("Core/Init.lua"), "ab12".to_string())]),
+            )])),
+            store: Some("elvui-13.21".to_string()),
+            previous_store: None,
+            stale: vec!["ElvUI_Options".to_string()],
+        };
+        write_manifest(addons.path(), ELVUI_SLUG, &manifest).unwrap();
+
+        assert!(addons.path().join(MANIFEST_FILE).is_file());
+        assert_eq!(read_manifest(addons.path(), ELVUI_SLUG).unwrap(), Some(manifest));
+        assert_eq!(read_manifest(addons.path(), "windtools").unwrap(), None);
+    }
+
+    #[test]
+    fn manifest_without_pin_still_parses() {
+        let manifest: Manifest = serde_json::from_str(
+            r#"{"version": "13.21", "installed_at": "2023-06-01T12:00:00Z", "directories": ["ElvUI"]}"#,
+        ).unwrap();
+        assert_eq!(manifest.pinned, None);
+    }
+}
@@ -0,0 +1,568 @@
+use clap::ArgEnum;
+use log::debug;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::time::SystemTime;
+
+use crate::cache::CacheEntry;
+use crate::error::{Error, Result};
+use crate::http::with_retries;
+use crate::version::{compare_versions, validate_version};
+
+/// The tukui API base URL used unless another one is given
+pub const TUKUI_API_URL: &str = "https://api.tukui.org/v1";
+
+/// The tukui slug of ElvUI itself
+pub const ELVUI_SLUG: &str = "elvui";
+
+const GITHUB_RELEASE_URL: &str = "https://api.github.com/repos/tukui-org/ElvUI/releases/latest";
+
+const GITHUB_RELEASES_URL: &str = "https://api.github.com/repos/tukui-org/ElvUI/releases?per_page=20";
+
+const GITHUB_TAG_URL: &str = "https://api.github.com/repos/tukui-org/ElvUI/releases/tags";
+
+/// The addon directories shipped in ElvUI releases, which GitHub doesn't list
+const ELVUI_DIRECTORIES: &[&str] = &["ElvUI", "ElvUI_Libraries", "ElvUI_Options"];
+
+#[derive(ArgEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Flavor {
+    Retail,
+    Wrath,
+    Cata,
+    Era,
+}
+
+impl Flavor {
+    /// The flavor directory under the WoW install root
+    pub fn install_dir(&self) -> &'static str {
+        match self {
+            Flavor::Retail => "_retail_",
+            Flavor::Wrath | Flavor::Cata => "_classic_",
+            Flavor::Era => "_classic_era_",
+        }
+    }
+
+    /// The flavor installed in the `install_dir` directory, unless it's
+    /// shared by several flavors like `_classic_`
+    pub fn from_install_dir(install_dir: &str) -> Option<Flavor> {
+        match install_dir {
+            "_retail_" => Some(Flavor::Retail),
+            "_classic_era_" => Some(Flavor::Era),
+            _ => None,
+        }
+    }
+
+    /// The product code that identifies this flavor in `.build.info`
+    pub fn product(&self) -> &'static str {
+        match self {
+            Flavor::Retail => "wow",
+            Flavor::Wrath | Flavor::Cata => "wow_classic",
+            Flavor::Era => "wow_classic_era",
+        }
+    }
+
+    /// The .toc file of the `addon` directory that carries the version
+    /// for this flavor
+    pub fn toc_file(&self, addon: &str) -> String {
+        match self {
+            Flavor::Retail => format!("{}_Mainline.toc", addon),
+            Flavor::Wrath => format!("{}_Wrath.toc", addon),
+            Flavor::Cata => format!("{}_Cata.toc", addon),
+            Flavor::Era => format!("{}_Vanilla.toc", addon),
+        }
+    }
+
+    /// The metadata URL of `slug` for this flavor on the tukui API at `api_url`
+    pub fn metadata_url(&self, api_url: &str, slug: &str) -> String {
+        match self {
+            Flavor::Retail => format!("{}/addon/{}", api_url, slug),
+            Flavor::Wrath => format!("{}/addon/{}?flavor=wrath", api_url, slug),
+            Flavor::Cata => format!("{}/addon/{}?flavor=cata", api_url, slug),
+            Flavor::Era => format!("{}/addon/{}?flavor=classic", api_url, slug),
+        }
+    }
+}
+
+#[derive(ArgEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Tukui,
+    Github,
+    /// The newest GitHub release, betas and alphas included. Chosen with
+    /// `--prerelease` rather than `--source`, since tukui has no such channel
+    #[clap(skip)]
+    #[serde(skip)]
+    GithubPrerelease,
+}
+
+impl Source {
+    pub fn is_github(&self) -> bool {
+        matches!(self, Source::Github | Source::GithubPrerelease)
+    }
+}
+
+/// A release of ElvUI, or of another addon, as described by the tukui API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElvuiMetadata {
+    pub slug: String,
+    pub name: String,
+    pub url: String,
+    pub version: String,
+    pub changelog_url: String,
+    pub ticket_url: String,
+    pub git_url: String,
+    pub last_update: String,
+    /// Missing from the response counts as empty, which `validate` rejects
+    /// with a clearer error than the parse would fail with
+    #[serde(default)]
+    pub directories: Vec<String>,
+    /// Whether this is a beta or alpha release rather than a stable one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub prerelease: bool,
+}
+
+impl ElvuiMetadata {
+    /// The directory whose .toc carries the addon's version: the one named
+    /// after the addon when there is one, otherwise the first listed.
+    pub fn main_directory(&self) -> &str {
+        self.directories
+            .iter()
+            .find(|dir| **dir == self.name)
+            .or_else(|| self.directories.first())
+            .map_or(&self.name, String::as_str)
+    }
+
+    /// When the release was last updated, from the date `last_update` starts
+    /// with: tukui gives `2023-06-01`, GitHub `2023-06-01T12:00:00Z`.
+    pub fn last_updated(&self) -> Option<SystemTime> {
+        let date = self.last_update.get(..10)?;
+        humantime::parse_rfc3339(&format!("{}T00:00:00Z", date)).ok()
+    }
+
+    /// Checks that the download URL, version and directories are usable,
+    /// so an API change fails loudly instead of installing nothing. `url`
+    /// is where the metadata came from, for the error.
+    pub fn validate(&self, url: &str) -> Result<()> {
+        let reason = if let Err(err) = reqwest::Url::parse(&self.url) {
+            format!("download URL `{}` is not a URL ({})", self.url, err)
+        } else if validate_version(&self.version).is_err() {
+            format!("version `{}` can't be parsed", self.version)
+        } else if self.directories.is_empty() {
+            "no addon directories are listed".to_string()
+        } else if let Some(dir) = self.directories.iter().find(|dir| !is_directory_name(dir)) {
+            format!("`{}` is not an addon directory name", dir)
+        } else {
+            return Ok(());
+        };
+
+        Err(Error::InvalidMetadata { url: url.to_string(), reason })
+    }
+}
+
+/// Whether `name` names a directory directly in the addons directory
+fn is_directory_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
+
+#[derive(Deserialize, Debug)]
+struct GithubRelease {
+    tag_name: String,
+    html_url: String,
+    published_at: String,
+    zipball_url: String,
+    assets: Vec<GithubAsset>,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    draft: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl From<GithubRelease> for ElvuiMetadata {
+    fn from(release: GithubRelease) -> Self {
+        let url = release
+            .assets
+            .iter()
+            .find(|asset| asset.name.ends_with(".zip"))
+            .map(|asset| asset.browser_download_url.clone())
+            .unwrap_or(release.zipball_url);
+
+        ElvuiMetadata {
+            slug: "elvui".to_string(),
+            name: "ElvUI".to_string(),
+            url,
+            version: release.tag_name.trim_start_matches('v').to_string(),
+            changelog_url: release.html_url.clone(),
+            ticket_url: "https://github.com/tukui-org/ElvUI/issues".to_string(),
+            git_url: "https://github.com/tukui-org/ElvUI".to_string(),
+            last_update: release.published_at,
+            directories: ELVUI_DIRECTORIES.iter().map(|dir| dir.to_string()).collect(),
+            prerelease: release.prerelease,
+        }
+    }
+}
+
+/// The newest of `releases` by version, skipping drafts and tags whose
+/// version can't be compared
+fn newest_release(url: &str, releases: Vec<GithubRelease>) -> Result<GithubRelease> {
+    let newest = releases
+        .into_iter()
+        .filter(|release| !release.draft && validate_version(&release.tag_name).is_ok())
+        .max_by(|a, b| compare_versions(&a.tag_name, &b.tag_name).unwrap_or(Ordering::Equal));
+
+    newest.ok_or_else(|| Error::InvalidMetadata { url: url.to_string(), reason: "no releases are listed".to_string() })
+}
+
+/// Fetches the latest release of the addon `slug` for `flavor`, retrying
+/// transient failures up to `retries` times. `source` only applies to
+/// ElvUI itself; other addons are always looked up on tukui.
+///
+/// `api_url` is tried before the built-in `TUKUI_API_URL`, e.g. after the
+/// API moved. When it answers with 404 or a server error, the built-in
+/// URL is tried next. Without `api_url` only the built-in URL is used.
+///
+/// GitHub rejects requests without a user agent, so `client` should be
+/// built with one, e.g. `http::DEFAULT_USER_AGENT`.
+pub fn fetch_metadata(
+    client: &Client,
+    slug: &str,
+    source: Source,
+    flavor: Flavor,
+    api_url: Option<&str>,
+    retries: u32,
+) -> Result<ElvuiMetadata> {
+    Ok(revalidate_metadata(client, slug, source, flavor, api_url, retries, None)?.metadata)
+}
+
+/// Like `fetch_metadata`, but when a `cached` response is given its `ETag`
+/// and `Last-Modified` are sent along, and a `304 Not Modified` answer
+/// reuses the cached metadata instead of downloading it again.
+pub fn revalidate_metadata(
+    client: &Client,
+    slug: &str,
+    source: Source,
+    flavor: Flavor,
+    api_url: Option<&str>,
+    retries: u32,
+    cached: Option<CacheEntry>,
+) -> Result<CacheEntry> {
+    let source = if slug == ELVUI_SLUG { source } else { Source::Tukui };
+    let mut urls = match source {
+        Source::Tukui => tukui_api_urls(api_url).iter().map(|base| flavor.metadata_url(base, slug)).collect(),
+        Source::Github => vec![GITHUB_RELEASE_URL.to_string()],
+        Source::GithubPrerelease => vec![GITHUB_RELEASES_URL.to_string()],
+    }
+    .into_iter()
+    .peekable();
+
+    let (url, response) = loop {
+        let url = urls.next().expect("there is at least one metadata URL");
+        debug!("Fetching metadata from {}", url);
+        match request_metadata(client, &url, retries, cached.as_ref()) {
+            Ok(response) => break (url, response),
+            Err(err) if urls.peek().is_some() && is_relocated(&err) => {
+                debug!("Fetching metadata from {} failed ({:#}), trying the next URL", url, err);
+            }
+            Err(err) => return Err(err),
+        }
+    };
+
+    let entry = match (response, cached) {
+        (None, Some(cached)) => {
+            debug!("Cached metadata revalidated, not modified since {}", cached.fetched_at);
+            CacheEntry { fetched_at: CacheEntry::now(), ..cached }
+        }
+        (Some(MetadataResponse { body, etag, last_modified }), _) => {
+            debug!("Metadata refreshed");
+            let metadata: ElvuiMetadata = match source {
+                Source::Tukui => parse_metadata(&url, &body)?,
+                Source::Github => parse_metadata::<GithubRelease>(&url, &body)?.into(),
+                Source::GithubPrerelease => newest_release(&url, parse_metadata(&url, &body)?)?.into(),
+            };
+            metadata.validate(&url)?;
+            CacheEntry { fetched_at: CacheEntry::now(), metadata, etag, last_modified }
+        }
+        (None, None) => unreachable!("304 is only accepted for cached metadata"),
+    };
+    debug!("json = {:#?}", entry.metadata);
+
+    Ok(entry)
+}
+
+struct MetadataResponse {
+    body: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Requests `url`, returning the body with its `ETag` and `Last-Modified`,
+/// or `None` when the server says `cached` is still current.
+fn request_metadata(
+    client: &Client,
+    url: &str,
+    retries: u32,
+    cached: Option<&CacheEntry>,
+) -> Result<Option<MetadataResponse>> {
+    with_retries(retries, || {
+        let mut request = client.get(url);
+        if let Some(cached) = cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request.send()?.error_for_status()?;
+        if response.status() == StatusCode::NOT_MODIFIED && cached.is_some() {
+            return Ok(None);
+        }
+
+        let etag = header_value(&response, ETAG);
+        let last_modified = header_value(&response, LAST_MODIFIED);
+        Ok(Some(MetadataResponse { body: response.text()?, etag, last_modified }))
+    })
+}
+
+/// The tukui API base URLs to try in order: `api_url` when given, then
+/// the built-in one
+fn tukui_api_urls(api_url: Option<&str>) -> Vec<&str> {
+    let mut urls: Vec<&str> = api_url.map(|url| url.trim_end_matches('/')).into_iter().collect();
+    if !urls.contains(&TUKUI_API_URL) {
+        urls.push(TUKUI_API_URL);
+    }
+    urls
+}
+
+/// Whether `err` suggests the API isn't at that URL, so another base URL
+/// may answer instead
+fn is_relocated(err: &Error) -> bool {
+    match err {
+        Error::Network(err) => err
+            .status()
+            .is_some_and(|status| status == StatusCode::NOT_FOUND || status.is_server_error()),
+        _ => false,
+    }
+}
+
+/// Fetches the ElvUI release tagged `v<version>` from GitHub, the only
+/// source that serves releases other than the latest one.
+pub fn fetch_github_release(client: &Client, version: &str, retries: u32) -> Result<ElvuiMetadata> {
+    let url = github_tag_url(version);
+    debug!("Fetching release from {}", url);
+
+    let body = with_retries(retries, || {
+        Ok(client.get(&url).send()?.error_for_status()?.text()?)
+    })?;
+
+    let metadata = ElvuiMetadata::from(parse_metadata::<GithubRelease>(&url, &body)?);
+    metadata.validate(&url)?;
+    Ok(metadata)
+}
+
+fn github_tag_url(version: &str) -> String {
+    format!("{}/v{}", GITHUB_TAG_URL, version.trim_start_matches('v'))
+}
+
+fn header_value(response: &Response, name: HeaderName) -> Option<String> {
+    response.headers().get(name)?.to_str().ok().map(str::to_string)
+}
+
+fn parse_metadata<T: DeserializeOwned>(url: &str, body: &str) -> Result<T> {
+    serde_json::from_str(body).map_err(|source| Error::MetadataParse { url: url.to_string(), source })
+}
+
+pub fn fetch_changelog(client: &Client, url: &str, retries: u32) -> Result<String> {
+    debug!("Fetching changelog from {}", url);
+    with_retries(retries, || Ok(client.get(url).send()?.error_for_status()?.text()?))
+}
+
+/// Keeps the first `max_lines` lines of the changelog.
+pub fn trim_changelog(changelog: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = changelog.trim().lines().collect();
+    if lines.len() <= max_lines {
+        return lines.join("\n");
+    }
+
+    format!("{}\n... ({} more lines)", lines[..max_lines].join("\n"), lines.len() - max_lines)
+}
+
+/// The entries of `changelog` for versions newer than `since`. Entries are
+/// expected newest first, each under a header like `### Version 13.21 [ June
+/// 1st 2023 ]` or `# 13.21`. Returns `None` when no header can be parsed or
+/// nothing is newer, so the caller can fall back to the whole changelog.
+pub fn changelog_since(changelog: &str, since: &str) -> Option<String> {
+    let header = Regex::new(r"(?i)^[ \t]*(?:#+[ \t]*(?:version[ \t]+)?|version[ \t]+)v?(?P<version>\d+(?:\.\d+)+)").unwrap();
+    let mut newer = Vec::new();
+    let mut found_header = false;
+
+    for line in changelog.trim().lines() {
+        if let Some(captures) = header.captures(line) {
+            found_header = true;
+            match compare_versions(&captures["version"], since) {
+                Ok(Ordering::Greater) => {}
+                Ok(_) => break,
+                Err(_) => return None,
+            }
+        }
+        if found_header {
+            newer.push(line);
+        }
+    }
+
+    debug!("{} changelog lines newer than {}", newer.len(), since);
+    if newer.is_empty() {
+        return None;
+    }
+    Some(newer.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_metadata;
+
+    #[test]
+    fn parses_last_update() {
+        let mut metadata = sample_metadata();
+        assert_eq!(metadata.last_updated(), humantime::parse_rfc3339("2023-06-01T00:00:00Z").ok());
+        metadata.last_update = "2023-06-01T12:00:00Z".to_string();
+        assert_eq!(metadata.last_updated(), humantime::parse_rfc3339("2023-06-01T00:00:00Z").ok());
+        metadata.last_update = "yesterday".to_string();
+        assert_eq!(metadata.last_updated(), None);
+    }
+
+    #[test]
+    fn github_release_converts_to_metadata() {
+        let release: GithubRelease = serde_json::from_str(r#"{
+            "tag_name": "v13.21",
+            "html_url": "https://github.com/tukui-org/ElvUI/releases/tag/v13.21",
+            "published_at": "2023-06-01T12:00:00Z",
+            "zipball_url": "https://api.github.com/repos/tukui-org/ElvUI/zipball/v13.21",
+            "assets": [{"name": "elvui-13.21.zip", "browser_download_url": "https://github.com/elvui-13.21.zip"}]
+        }"#).unwrap();
+
+        let metadata = ElvuiMetadata::from(release);
+        assert_eq!(metadata.version, "13.21");
+        assert_eq!(metadata.url, "https://github.com/elvui-13.21.zip");
+        assert!(metadata.directories.contains(&"ElvUI".to_string()));
+    }
+
+    #[test]
+    fn newest_release_includes_prereleases() {
+        let releases: Vec<GithubRelease> = serde_json::from_str(r#"[
+            {"tag_name": "v13.22", "html_url": "", "published_at": "", "zipball_url": "", "assets": [], "draft": true},
+            {"tag_name": "v13.21", "html_url": "", "published_at": "", "zipball_url": "", "assets": []},
+            {"tag_name": "v13.22-beta1", "html_url": "", "published_at": "", "zipball_url": "", "assets": [], "prerelease": true},
+            {"tag_name": "nightly", "html_url": "", "published_at": "", "zipball_url": "", "assets": []}
+        ]"#).unwrap();
+
+        let metadata = ElvuiMetadata::from(newest_release(GITHUB_RELEASES_URL, releases).unwrap());
+        assert_eq!(metadata.version, "13.22-beta1");
+        assert!(metadata.prerelease);
+        assert!(newest_release(GITHUB_RELEASES_URL, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn github_tag_url_adds_v_prefix() {
+        assert_eq!(github_tag_url("13.40"), format!("{}/v13.40", GITHUB_TAG_URL));
+        assert_eq!(github_tag_url("v13.40"), format!("{}/v13.40", GITHUB_TAG_URL));
+    }
+
+    #[test]
+    fn metadata_url_uses_slug() {
+        assert_eq!(Flavor::Retail.metadata_url(TUKUI_API_URL, "elvui"), "https://api.tukui.org/v1/addon/elvui");
+        assert_eq!(Flavor::Era.metadata_url(TUKUI_API_URL, "windtools"), "https://api.tukui.org/v1/addon/windtools?flavor=classic");
+    }
+
+    #[test]
+    fn given_api_url_is_tried_before_built_in() {
+        assert_eq!(tukui_api_urls(None), vec![TUKUI_API_URL]);
+        assert_eq!(tukui_api_urls(Some("https://tukui.example/v2/")), vec!["https://tukui.example/v2", TUKUI_API_URL]);
+        assert_eq!(tukui_api_urls(Some(TUKUI_API_URL)), vec![TUKUI_API_URL]);
+        assert!(!is_relocated(&Error::install("not a response")));
+    }
+
+    #[test]
+    fn main_directory_prefers_addon_name() {
+        let mut metadata = sample_metadata();
+        metadata.directories = vec!["ElvUI_Libraries".to_string(), "ElvUI".to_string()];
+        assert_eq!(metadata.main_directory(), "ElvUI");
+
+        metadata.name = "WindTools".to_string();
+        metadata.directories = vec!["ElvUI_WindTools".to_string()];
+        assert_eq!(metadata.main_directory(), "ElvUI_WindTools");
+    }
+
+    #[test]
+    fn rejects_unusable_metadata() {
+        let url = "https://api.tukui.org/v1/addon/elvui";
+        assert!(sample_metadata().validate(url).is_ok());
+
+        let invalid = [
+            ElvuiMetadata { url: String::new(), ..sample_metadata() },
+            ElvuiMetadata { version: String::new(), ..sample_metadata() },
+            ElvuiMetadata { version: "latest".to_string(), ..sample_metadata() },
+            ElvuiMetadata { directories: Vec::new(), ..sample_metadata() },
+            ElvuiMetadata { directories: vec!["ElvUI".to_string(), "../ElvUI".to_string()], ..sample_metadata() },
+        ];
+        for metadata in invalid {
+            let err = metadata.validate(url).unwrap_err();
+            assert!(matches!(err, Error::InvalidMetadata { .. }), "{:?}", metadata);
+        }
+    }
+
+    #[test]
+    fn trims_long_changelogs() {
+        let changelog = "### 13.21\n- fix\n- fix\n### 13.20\n- fix\n";
+        assert_eq!(trim_changelog(changelog, 10), changelog.trim());
+        assert_eq!(trim_changelog(changelog, 2), "### 13.21\n- fix\n... (3 more lines)");
+    }
+
+    #[test]
+    fn changelog_since_keeps_newer_entries() {
+        let changelog = "# ElvUI\n### Version 13.22 [ June 8th 2023 ]\n- fix\n### Version 13.21 [ June 1st 2023 ]\n- fix\n### Version 13.20\n- fix\n";
+        assert_eq!(
+            changelog_since(changelog, "13.20").as_deref(),
+            Some("### Version 13.22 [ June 8th 2023 ]\n- fix\n### Version 13.21 [ June 1st 2023 ]\n- fix")
+        );
+        assert_eq!(changelog_since(changelog, "13.22"), None);
+        assert_eq!(changelog_since("- fix\n- another fix", "13.20"), None);
+    }
+
+    #[test]
+    fn missing_directories_fail_validation() {
+        let mut value = serde_json::to_value(sample_metadata()).unwrap();
+        value.as_object_mut().unwrap().remove("directories");
+        let metadata: ElvuiMetadata = serde_json::from_value(value).unwrap();
+
+        let err = metadata.validate(TUKUI_API_URL).unwrap_err();
+        assert!(err.to_string().contains("no addon directories"), "{}", err);
+    }
+
+    #[test]
+    fn unexpected_metadata_is_a_parse_error() {
+        let err = parse_metadata::<ElvuiMetadata>("https://api.tukui.org/v1/addon/elvui", "<html>").unwrap_err();
+        assert!(matches!(err, Error::MetadataParse { .. }));
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn check_metadata() {
+        let result = fetch_metadata(&Client::new(), ELVUI_SLUG, Source::Tukui, Flavor::Retail, None, 0);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap().version, "12.66");
+    }
+}
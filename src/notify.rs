@@ -0,0 +1,14 @@
+use log::{debug, info};
+use notify_rust::Notification;
+
+/// Shows a desktop notification that `name` was updated to `version`. When
+/// none can be shown, e.g. on a headless system, the message is logged
+/// instead.
+pub fn notify_installed(name: &str, version: &str) {
+    let message = format!("{} updated to {}", name, version);
+    let shown = Notification::new().appname(env!("CARGO_PKG_NAME")).summary(&message).show();
+    if let Err(err) = shown {
+        debug!("Unable to show a desktop notification: {}", err);
+        info!("{}", message);
+    }
+}
@@ -0,0 +1,195 @@
+use log::debug;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Flavor;
+
+const MACOS_INSTALL_ROOT: &str = "/Applications/World of Warcraft";
+
+const WINDOWS_INSTALL_ROOTS: &[&str] = &[
+    r"C:\Program Files (x86)",
+    r"C:\Program Files",
+];
+
+/// The standard addons directory for `flavor` on this platform, if one
+/// can be found. Installs listed in the Battle.net launcher config come
+/// first, then the usual locations.
+pub fn default_addons_path(flavor: Flavor) -> Option<PathBuf> {
+    let found = discover_wow_roots()
+        .iter()
+        .map(|root| root.join(flavor.install_dir()).join("Interface"))
+        .find_map(|interface| find_addons_dir(&interface));
+    if found.is_some() || cfg!(target_os = "windows") {
+        return found;
+    }
+
+    Some(PathBuf::from(MACOS_INSTALL_ROOT).join(flavor.install_dir()).join("Interface/Addons"))
+}
+
+/// The addons directory of the WoW flavor installed in `wow_dir`, the
+/// directory containing `Interface`, e.g. `World of Warcraft/_retail_`
+pub fn wow_dir_addons_path(wow_dir: &Path) -> PathBuf {
+    let interface = wow_dir.join("Interface");
+    find_addons_dir(&interface).unwrap_or_else(|| interface.join("AddOns"))
+}
+
+/// The flavor installed in `wow_dir`, when its name tells
+pub fn wow_dir_flavor(wow_dir: &Path) -> Option<Flavor> {
+    Flavor::from_install_dir(wow_dir.file_name()?.to_str()?)
+}
+
+/// Every `World of Warcraft` directory found on this machine, without
+/// duplicates.
+pub fn discover_wow_roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for root in install_roots().iter().map(|root| root.join("World of Warcraft")) {
+        if root.is_dir() && !roots.contains(&root) {
+            debug!("Found WoW install {:?} with flavors {:?}", &root, flavor_dirs(&root));
+            roots.push(root);
+        }
+    }
+    roots
+}
+
+/// The flavor directories in the WoW install `root`, e.g. `_retail_`
+pub fn flavor_dirs(root: &Path) -> Vec<String> {
+    let mut dirs: Vec<String> = match std::fs::read_dir(root) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.len() > 2 && name.starts_with('_') && name.ends_with('_'))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    dirs.sort();
+    dirs
+}
+
+/// Where elvui-manager caches metadata and downloads: `LOCALAPPDATA` on
+/// Windows, and `XDG_CACHE_HOME` or `~/.cache` elsewhere.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_dir = if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var_os("LOCALAPPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+        }
+    };
+
+    Some(cache_dir.join("elvui-manager"))
+}
+
+/// The standard location of `config.toml`: under `APPDATA` on Windows, and
+/// `XDG_CONFIG_HOME` or `~/.config` elsewhere.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+
+    Some(config_dir.join("elvui-manager").join("config.toml"))
+}
+
+/// The directories WoW may be installed in: where the Battle.net launcher
+/// installs games, then the platform's standard locations.
+fn install_roots() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+
+    if let Some(config_path) = battle_net_config_path() {
+        if let Ok(content) = std::fs::read_to_string(&config_path) {
+            debug!("Using Battle.net config: {:?}", &config_path);
+            match battle_net_install_root(&content) {
+                Some(root) => roots.push(root),
+                None => debug!("No install path found in {:?}", &config_path),
+            }
+        }
+    }
+
+    if cfg!(target_os = "windows") {
+        roots.extend(WINDOWS_INSTALL_ROOTS.iter().map(PathBuf::from));
+    } else if let Some(applications) = Path::new(MACOS_INSTALL_ROOT).parent() {
+        roots.push(applications.to_path_buf());
+    }
+    roots
+}
+
+fn battle_net_config_path() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        let appdata = std::env::var_os("APPDATA")?;
+        Some(PathBuf::from(appdata).join("Battle.net").join("Battle.net.config"))
+    } else if cfg!(target_os = "macos") {
+        let home = std::env::var_os("HOME")?;
+        Some(PathBuf::from(home).join("Library/Application Support/Battle.net/Battle.net.config"))
+    } else {
+        None
+    }
+}
+
+fn battle_net_install_root(config: &str) -> Option<PathBuf> {
+    let config: serde_json::Value = serde_json::from_str(config).ok()?;
+    let root = config.pointer("/Client/Install/DefaultInstallPath")?.as_str()?;
+    Some(PathBuf::from(root))
+}
+
+/// Finds the addons directory under `interface`, which is spelled either
+/// `AddOns` or `Addons` depending on the install.
+fn find_addons_dir(interface: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(interface)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.is_dir() && path.file_name().is_some_and(|name| name.eq_ignore_ascii_case("addons"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn find_addons_dir_ignores_case() {
+        let interface = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::create_dir(interface.path().join("AddOns")).unwrap();
+
+        let found = find_addons_dir(interface.path()).unwrap();
+        assert_eq!(found, interface.path().join("AddOns"));
+    }
+
+    #[test]
+    fn addons_path_from_wow_dir() {
+        let root = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let wow_dir = root.path().join("_classic_era_");
+        assert_eq!(wow_dir_addons_path(&wow_dir), wow_dir.join("Interface/AddOns"));
+
+        std::fs::create_dir_all(wow_dir.join("Interface/Addons")).unwrap();
+        assert_eq!(wow_dir_addons_path(&wow_dir), wow_dir.join("Interface/Addons"));
+
+        assert_eq!(wow_dir_flavor(&wow_dir), Some(Flavor::Era));
+        assert_eq!(wow_dir_flavor(&root.path().join("_classic_")), None);
+    }
+
+    #[test]
+    fn flavor_dirs_lists_installed_flavors() {
+        let root = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        for dir in ["_retail_", "_classic_era_", "Data", "_"] {
+            std::fs::create_dir(root.path().join(dir)).unwrap();
+        }
+        std::fs::write(root.path().join("_ptr_"), "").unwrap();
+
+        assert_eq!(flavor_dirs(root.path()), vec!["_classic_era_", "_retail_"]);
+    }
+
+    #[test]
+    fn battle_net_config_install_root() {
+        let config = r#"{"Client": {"Install": {"DefaultInstallPath": "D:/Games"}}}"#;
+        assert_eq!(battle_net_install_root(config), Some(PathBuf::from("D:/Games")));
+        assert_eq!(battle_net_install_root("{}"), None);
+    }
+}
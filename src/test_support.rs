@@ -0,0 +1,38 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::metadata::ElvuiMetadata;
+
+pub fn sample_metadata() -> ElvuiMetadata {
+    ElvuiMetadata {
+        slug: "elvui".to_string(),
+        name: "ElvUI".to_string(),
+        url: "https://api.tukui.org/v1/download/dev/elvui/main".to_string(),
+        version: "13.21".to_string(),
+        changelog_url: "https://api.tukui.org/v1/changelog/elvui".to_string(),
+        ticket_url: "https://github.com/tukui-org/ElvUI/issues".to_string(),
+        git_url: "https://github.com/tukui-org/ElvUI".to_string(),
+        last_update: "2023-06-01".to_string(),
+        directories: vec!["ElvUI".to_string(), "ElvUI_Options".to_string()],
+        prerelease: false,
+    }
+}
+
+pub fn create_addon(root: &Path, name: &str, marker: &str) {
+    std::fs::create_dir_all(root.join(name)).unwrap();
+    std::fs::write(root.join(name).join("marker"), marker).unwrap();
+}
+
+pub fn read_marker(root: &Path, name: &str) -> String {
+    std::fs::read_to_string(root.join(name).join("marker")).unwrap()
+}
+
+pub fn write_zip(path: &Path, entries: &[(&str, &str)]) {
+    let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+    for (name, content) in entries {
+        zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
@@ -0,0 +1,219 @@
+use log::debug;
+use regex::Regex;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::metadata::{ElvuiMetadata, Flavor};
+
+/// The fields of a `.toc` file this tool cares about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocMetadata {
+    pub version: String,
+    pub title: Option<String>,
+    /// The game builds the addon was made for, e.g. `100107` for 10.1.7,
+    /// as listed in `## Interface: 100207, 110000`
+    pub interfaces: Vec<u32>,
+}
+
+impl TocMetadata {
+    /// Whether the addon declares an interface at least as new as the
+    /// client's `interface`, so the game won't list it as out of date.
+    /// Without any declared interface there is nothing to go by.
+    pub fn supports_interface(&self, interface: u32) -> bool {
+        self.interfaces.is_empty() || self.interfaces.iter().any(|declared| *declared >= interface)
+    }
+}
+
+/// Reads the installed ElvUI version from the `.toc` file for `flavor`.
+pub fn fetch_installed_version(addons_path: &Path, flavor: Flavor) -> Result<String> {
+    Ok(read_toc(addons_path, "ElvUI", flavor)?.version)
+}
+
+/// Reads the installed version of the addon described by `metadata` from
+/// the `.toc` file in its main directory.
+pub fn fetch_addon_version(addons_path: &Path, metadata: &ElvuiMetadata, flavor: Flavor) -> Result<String> {
+    Ok(fetch_addon_toc(addons_path, metadata, flavor)?.version)
+}
+
+/// Parses the `.toc` file in the main directory of the addon described by
+/// `metadata`.
+pub fn fetch_addon_toc(addons_path: &Path, metadata: &ElvuiMetadata, flavor: Flavor) -> Result<TocMetadata> {
+    read_toc(addons_path, metadata.main_directory(), flavor)
+}
+
+/// Parses the flavor's `.toc` in the `addon` directory, falling back to the
+/// plain `<addon>.toc` that single-flavor addons ship.
+fn read_toc(addons_path: &Path, addon: &str, flavor: Flavor) -> Result<TocMetadata> {
+    let dir = addons_path.join(addon);
+    let mut path = dir.join(flavor.toc_file(addon));
+    if !path.is_file() && dir.join(format!("{}.toc", addon)).is_file() {
+        path = dir.join(format!("{}.toc", addon));
+    }
+
+    debug!("Using path: {:?}", &path);
+    parse_toc(&path)
+}
+
+/// Reads the version, title and interface from the `.toc` file at `path`.
+/// Only the version is required.
+pub fn parse_toc(path: &Path) -> Result<TocMetadata> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(source) => return Err(Error::TocNotFound { path: path.to_path_buf(), source }),
+    };
+
+    let version = parse_version(&content).ok_or_else(|| Error::VersionParse { path: path.to_path_buf() })?;
+    let content = strip_color_codes(&content);
+    let title = toc_field(&content, "Title").filter(|title| !title.is_empty());
+    let interfaces = match toc_field(&content, "Interface") {
+        Some(interface) => interface.split(',').filter_map(|build| build.trim().parse().ok()).collect(),
+        None => Vec::new(),
+    };
+
+    Ok(TocMetadata { version, title, interfaces })
+}
+
+/// Finds the numeric version in a `Version:` field, with or without the
+/// leading `##`, ignoring anything after it such as a build suffix.
+pub fn parse_version(content: &str) -> Option<String> {
+    let content = strip_color_codes(content);
+    let re = Regex::new(r"(?m)^[ \t]*(?:##[ \t]*)?Version:[ \t]*(?P<version>\d+(?:\.\d+)*)").unwrap();
+    let caps = re.captures(&content)?;
+
+    Some(caps["version"].to_string())
+}
+
+/// The trimmed value of the `## <name>:` field, if the file has one.
+fn toc_field(content: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(r"(?m)^[ \t]*(?:##[ \t]*)?{}:(?P<value>.*)$", regex::escape(name))).unwrap();
+    let caps = re.captures(content)?;
+
+    Some(caps["value"].trim().to_string())
+}
+
+/// Removes WoW color escapes (`|cAARRGGBB` and `|r`) from `.toc` text.
+fn strip_color_codes(content: &str) -> String {
+    let re = Regex::new(r"\|c[[:xdigit:]]{8}|\|r").unwrap();
+    re.replace_all(content, "").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_metadata;
+    use tempfile::Builder;
+
+    #[test]
+    fn installed_version_uses_flavor_toc() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::create_dir(addons.path().join("ElvUI")).unwrap();
+        std::fs::write(addons.path().join("ElvUI/ElvUI_Vanilla.toc"), "## Version: 1.10\n").unwrap();
+
+        assert_eq!(fetch_installed_version(addons.path(), Flavor::Era).unwrap(), "1.10");
+        assert!(fetch_installed_version(addons.path(), Flavor::Retail).is_err());
+    }
+
+    #[test]
+    fn addon_version_falls_back_to_plain_toc() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::create_dir(addons.path().join("ElvUI_WindTools")).unwrap();
+        std::fs::write(addons.path().join("ElvUI_WindTools/ElvUI_WindTools.toc"), "## Version: 3.50\n").unwrap();
+
+        let mut metadata = sample_metadata();
+        metadata.name = "WindTools".to_string();
+        metadata.directories = vec!["ElvUI_WindTools".to_string()];
+        assert_eq!(fetch_addon_version(addons.path(), &metadata, Flavor::Retail).unwrap(), "3.50");
+    }
+
+    #[test]
+    fn installed_version_strips_color_codes() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::create_dir(addons.path().join("ElvUI")).unwrap();
+        std::fs::write(
+            addons.path().join("ElvUI/ElvUI_Mainline.toc"),
+            "## Title: |cff1784d1ElvUI|r\n## Version: |cffFFFFFF13.00|r\n",
+        ).unwrap();
+
+        assert_eq!(fetch_installed_version(addons.path(), Flavor::Retail).unwrap(), "13.00");
+    }
+
+    #[test]
+    fn parses_title_and_interface() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let path = addons.path().join("ElvUI_Mainline.toc");
+        std::fs::write(
+            &path,
+            "## Interface: 100100, 100107\r\n## Title: |cff1784d1ElvUI|r\r\n## Version: 13.41\r\n",
+        ).unwrap();
+
+        assert_eq!(parse_toc(&path).unwrap(), TocMetadata {
+            version: "13.41".to_string(),
+            title: Some("ElvUI".to_string()),
+            interfaces: vec![100100, 100107],
+        });
+
+        std::fs::write(&path, "## Version: 13.41\n## Interface: unknown\n").unwrap();
+        let toc = parse_toc(&path).unwrap();
+        assert_eq!(toc.title, None);
+        assert_eq!(toc.interfaces, Vec::<u32>::new());
+    }
+
+    #[test]
+    fn any_declared_interface_supports_client() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let path = addons.path().join("ElvUI_Mainline.toc");
+        std::fs::write(
+            &path,
+            "## Interface: 100207, 110000\n## Title: |cff1784d1ElvUI|r\n## Version: 13.74\n## Author: Elv, Simpy\n",
+        ).unwrap();
+
+        let toc = parse_toc(&path).unwrap();
+        assert_eq!(toc.interfaces, vec![100207, 110000]);
+        assert!(toc.supports_interface(100207));
+        assert!(toc.supports_interface(110000));
+        assert!(!toc.supports_interface(110002));
+
+        let toc = TocMetadata { interfaces: Vec::new(), ..toc };
+        assert!(toc.supports_interface(110002));
+    }
+
+    #[test]
+    fn latest_version_404() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+
+        let err = fetch_installed_version(addons.path(), Flavor::Retail).unwrap_err();
+        assert!(matches!(err, Error::TocNotFound { .. }));
+        assert!(err.to_string().starts_with("could not read file"));
+    }
+
+    #[test]
+    fn latest_version_no_version_div() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        std::fs::create_dir(addons.path().join("ElvUI")).unwrap();
+        std::fs::write(addons.path().join("ElvUI/ElvUI_Mainline.toc"), "## Title: ElvUI\n").unwrap();
+
+        let err = fetch_installed_version(addons.path(), Flavor::Retail).unwrap_err();
+        assert!(matches!(err, Error::VersionParse { .. }));
+        assert!(err.to_string().starts_with("could not find Version field"));
+    }
+
+    #[test]
+    fn latest_version_no_bold_div() {
+        assert_eq!(parse_version("## Version:\n## Author: Elv\n"), None);
+        assert_eq!(parse_version("## Version: |r\n"), None);
+    }
+
+    #[test]
+    fn version_tolerates_unusual_layouts() {
+        assert_eq!(parse_version("## Version: 13.00\n").as_deref(), Some("13.00"));
+        assert_eq!(parse_version("Version:  13.00 (build 5)\n").as_deref(), Some("13.00"));
+        assert_eq!(parse_version("##   Version:\t13.01-beta\n").as_deref(), Some("13.01"));
+        assert_eq!(parse_version("## X-Curse-Version: 13.00\n"), None);
+        assert_eq!(parse_version("## Version:\n13.00\n"), None);
+    }
+
+    #[test]
+    fn latest_version_bad_version() {
+        assert_eq!(parse_version("## Version: unknown\n"), None);
+    }
+}
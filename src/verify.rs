@@ -0,0 +1,176 @@
+use log::debug;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::archive::sha256_file;
+use crate::error::Result;
+
+/// How an installed addon directory differs from the release, with paths
+/// relative to the directory
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct DirectoryDiff {
+    pub directory: String,
+    /// In the release but not installed
+    pub missing: Vec<PathBuf>,
+    /// Installed but not in the release, such as settings saved by the addon
+    pub extra: Vec<PathBuf>,
+    /// Installed with different contents than the release
+    pub modified: Vec<PathBuf>,
+}
+
+impl DirectoryDiff {
+    pub fn is_clean(&self) -> bool {
+        !self.is_damaged() && self.extra.is_empty()
+    }
+
+    /// Whether files of the release are missing or modified, which extra
+    /// files alone don't count as.
+    pub fn is_damaged(&self) -> bool {
+        !self.missing.is_empty() || !self.modified.is_empty()
+    }
+}
+
+/// The SHA-256 of every file in a directory, by its path relative to it
+pub type FileHashes = BTreeMap<PathBuf, String>;
+
+/// Compares every file of `directories` under `installed_root` with the
+/// release extracted to `release_root`, by SHA-256. Nothing is changed.
+pub fn compare_directories(release_root: &Path, installed_root: &Path, directories: &[String]) -> Result<Vec<DirectoryDiff>> {
+    let mut diffs = Vec::new();
+
+    for target in directories {
+        let release = file_hashes(&release_root.join(target))?;
+        let installed = file_hashes(&installed_root.join(target))?;
+        debug!("{}: {} files in the release, {} installed", target, release.len(), installed.len());
+        diffs.push(diff_hashes(target, &release, &installed));
+    }
+
+    Ok(diffs)
+}
+
+/// Like `compare_directories`, but against the hashes recorded by
+/// `directory_hashes` at install time, so nothing is downloaded. A
+/// directory without recorded hashes counts as all extra.
+pub fn compare_with_hashes(
+    recorded: &BTreeMap<String, FileHashes>,
+    installed_root: &Path,
+    directories: &[String],
+) -> Result<Vec<DirectoryDiff>> {
+    let none = FileHashes::new();
+    let mut diffs = Vec::new();
+
+    for target in directories {
+        let release = recorded.get(target).unwrap_or(&none);
+        let installed = file_hashes(&installed_root.join(target))?;
+        debug!("{}: {} files recorded, {} installed", target, release.len(), installed.len());
+        diffs.push(diff_hashes(target, release, &installed));
+    }
+
+    Ok(diffs)
+}
+
+/// The hashes of every file of `directories` under `root`, as recorded in
+/// the manifest for `compare_with_hashes`.
+pub fn directory_hashes(root: &Path, directories: &[String]) -> Result<BTreeMap<String, FileHashes>> {
+    directories
+        .iter()
+        .map(|target| Ok((target.clone(), file_hashes(&root.join(target))?)))
+        .collect()
+}
+
+fn diff_hashes(directory: &str, release: &FileHashes, installed: &FileHashes) -> DirectoryDiff {
+    let mut diff = DirectoryDiff { directory: directory.to_string(), ..Default::default() };
+    for (path, hash) in release {
+        match installed.get(path) {
+            None => diff.missing.push(path.clone()),
+            Some(installed_hash) if installed_hash != hash => diff.modified.push(path.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.extra = installed.keys().filter(|path| !release.contains_key(*path)).cloned().collect();
+    diff
+}
+
+/// The SHA-256 of every file under `dir` by its path relative to `dir`, or
+/// nothing when `dir` doesn't exist.
+fn file_hashes(dir: &Path) -> Result<FileHashes> {
+    let mut hashes = BTreeMap::new();
+    if dir.is_dir() {
+        collect_hashes(dir, dir, &mut hashes)?;
+    }
+    Ok(hashes)
+}
+
+fn collect_hashes(root: &Path, dir: &Path, hashes: &mut FileHashes) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_hashes(root, &path, hashes)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            hashes.insert(relative, sha256_file(&path)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::create_addon;
+    use tempfile::Builder;
+
+    #[test]
+    fn reports_missing_extra_and_modified_files() {
+        let release = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let installed = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        for root in [release.path(), installed.path()] {
+            create_addon(root, "ElvUI", "13.21");
+            create_addon(root, "ElvUI_Options", "13.21");
+            std::fs::create_dir(root.join("ElvUI/Core")).unwrap();
+            std::fs::write(root.join("ElvUI/Core/Init.lua"), "init").unwrap();
+        }
+        std::fs::write(release.path().join("ElvUI/Core/Gone.lua"), "gone").unwrap();
+        std::fs::write(installed.path().join("ElvUI/Core/Init.lua"), "edited").unwrap();
+        std::fs::write(installed.path().join("ElvUI/Settings.lua"), "mine").unwrap();
+
+        let directories = vec!["ElvUI".to_string(), "ElvUI_Options".to_string()];
+        let diffs = compare_directories(release.path(), installed.path(), &directories).unwrap();
+
+        assert_eq!(diffs[0], DirectoryDiff {
+            directory: "ElvUI".to_string(),
+            missing: vec![PathBuf::from("Core/Gone.lua")],
+            extra: vec![PathBuf::from("Settings.lua")],
+            modified: vec![PathBuf::from("Core/Init.lua")],
+        });
+        assert!(diffs[1].is_clean());
+    }
+
+    #[test]
+    fn compares_with_recorded_hashes() {
+        let addons = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(addons.path(), "ElvUI", "13.21");
+        std::fs::write(addons.path().join("ElvUI/Init.lua"), "init").unwrap();
+        let directories = vec!["ElvUI".to_string()];
+        let recorded = directory_hashes(addons.path(), &directories).unwrap();
+        assert!(compare_with_hashes(&recorded, addons.path(), &directories).unwrap()[0].is_clean());
+
+        std::fs::write(addons.path().join("ElvUI/Init.lua"), "edited").unwrap();
+        std::fs::remove_file(addons.path().join("ElvUI/marker")).unwrap();
+        let diffs = compare_with_hashes(&recorded, addons.path(), &directories).unwrap();
+        assert_eq!(diffs[0].missing, vec![PathBuf::from("marker")]);
+        assert_eq!(diffs[0].modified, vec![PathBuf::from("Init.lua")]);
+    }
+
+    #[test]
+    fn missing_directory_is_all_missing() {
+        let release = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        let installed = Builder::new().prefix("elvui-manager-test").tempdir().unwrap();
+        create_addon(release.path(), "ElvUI", "13.21");
+
+        let diffs = compare_directories(release.path(), installed.path(), &["ElvUI".to_string()]).unwrap();
+        assert_eq!(diffs[0].missing, vec![PathBuf::from("marker")]);
+    }
+}
@@ -0,0 +1,110 @@
+use log::debug;
+use std::cmp::Ordering;
+use version_compare::{Cmp, Version};
+
+use crate::error::{Error, Result};
+
+/// Compares two versions like `13.21`, allowing a leading `v`, a
+/// pre-release suffix such as `13.21-beta` (older than `13.21`) and build
+/// metadata after a `+`, which is ignored. Fails with
+/// `Error::InvalidVersion` on anything else that can't be parsed.
+pub fn compare_versions(a: &str, b: &str) -> Result<Ordering> {
+    let (a_release, a_pre) = split_version(a);
+    let (b_release, b_pre) = split_version(b);
+
+    let release = compare_parts(parse(a, a_release)?, parse(b, b_release)?);
+    let ordering = match (release, a_pre, b_pre) {
+        (Ordering::Equal, None, Some(_)) => Ordering::Greater,
+        (Ordering::Equal, Some(_), None) => Ordering::Less,
+        (Ordering::Equal, Some(a_pre), Some(b_pre)) => match (Version::from(a_pre), Version::from(b_pre)) {
+            (Some(a_pre), Some(b_pre)) => compare_parts(a_pre, b_pre),
+            _ => a_pre.cmp(b_pre),
+        },
+        (release, _, _) => release,
+    };
+
+    debug!("Comparing {} to {}: {:?}", a, b, ordering);
+    Ok(ordering)
+}
+
+pub fn is_outdated(installed_version: &str, latest_version: &str) -> Result<bool> {
+    Ok(compare_versions(installed_version, latest_version)? == Ordering::Less)
+}
+
+pub fn same_version(a: &str, b: &str) -> bool {
+    match compare_versions(a, b) {
+        Ok(ordering) => ordering == Ordering::Equal,
+        Err(_) => a == b,
+    }
+}
+
+/// Fails with `Error::InvalidVersion` unless `version` is one that
+/// `compare_versions` can compare.
+pub fn validate_version(version: &str) -> Result<()> {
+    parse(version, split_version(version).0).map(|_| ())
+}
+
+/// Splits `v13.21-beta+abc123` into `13.21` and `Some("beta")`.
+fn split_version(version: &str) -> (&str, Option<&str>) {
+    let version = version.trim();
+    let version = version.strip_prefix(['v', 'V']).unwrap_or(version);
+    let version = version.split('+').next().unwrap_or(version);
+
+    match version.split_once('-') {
+        Some((release, pre)) => (release, Some(pre)),
+        None => (version, None),
+    }
+}
+
+fn parse<'a>(version: &str, release: &'a str) -> Result<Version<'a>> {
+    let invalid = || Error::InvalidVersion { version: version.to_string() };
+    if !release.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    Version::from(release).ok_or_else(invalid)
+}
+
+fn compare_parts(a: Version, b: Version) -> Ordering {
+    match a.compare(b) {
+        Cmp::Lt => Ordering::Less,
+        Cmp::Gt => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions() {
+        assert!(is_outdated("13.20", "13.21").unwrap());
+        assert!(!is_outdated("13.21", "13.21").unwrap());
+        assert!(!is_outdated("13.22", "13.21").unwrap());
+    }
+
+    #[test]
+    fn compares_suffixed_versions() {
+        assert!(is_outdated("13.21", "13.21.1").unwrap());
+        assert!(is_outdated("13.21-beta", "13.21").unwrap());
+        assert!(!is_outdated("13.21", "13.21-beta").unwrap());
+        assert!(is_outdated("13.21-beta.2", "13.21-beta.10").unwrap());
+        assert!(is_outdated("v13.20", "13.21+build.5").unwrap());
+    }
+
+    #[test]
+    fn rejects_unparseable_versions() {
+        let err = is_outdated("13.21", "").unwrap_err();
+        assert!(matches!(err, Error::InvalidVersion { .. }));
+        assert!(is_outdated("latest", "13.21").is_err());
+    }
+
+    #[test]
+    fn matches_versions() {
+        assert!(same_version("13.21", "13.21"));
+        assert!(same_version("v13.21", "13.21+abc"));
+        assert!(!same_version("13.20", "13.21"));
+        assert!(!same_version("13.21-beta", "13.21"));
+        assert!(same_version("latest", "latest"));
+    }
+}